@@ -0,0 +1,7 @@
+pub mod delay;
+pub mod drift;
+pub mod limiter;
+pub mod meter;
+pub mod resample;
+pub mod smooth;
+pub mod underrun;
@@ -0,0 +1,174 @@
+use crate::Sample;
+
+/// Longest delay we can give, in samples. Must be a power of two.
+pub const MAX_DELAY: usize = 8192;
+
+/// Plain whole-sample delay, used to keep a dry signal lined up with one that
+/// has been through a channel.
+pub struct DelayLine {
+    buffer: Vec<Sample>,
+    write: usize,
+}
+
+impl DelayLine {
+    pub fn new() -> Self {
+        Self {
+            buffer: vec![[0.0; 2]; MAX_DELAY],
+            write: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.fill([0.0; 2]);
+    }
+
+    /// Push a sample in and get back the one from `delay` samples ago
+    pub fn tick(&mut self, s: Sample, delay: usize) -> Sample {
+        self.buffer[self.write] = s;
+        let out =
+            self.buffer[(self.write + MAX_DELAY - delay.min(MAX_DELAY - 1)) & (MAX_DELAY - 1)];
+        self.write = (self.write + 1) & (MAX_DELAY - 1);
+        out
+    }
+}
+
+impl Default for DelayLine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Delay that can be any length, including fractions of a sample, and can be
+/// swept without clicking. Reads between samples with cubic interpolation.
+pub struct FractionalDelayLine {
+    buffer: Vec<Sample>,
+    write: usize,
+}
+
+impl FractionalDelayLine {
+    /// Make a delay line that can delay by up to `max_delay` samples
+    pub fn new(max_delay: usize) -> Self {
+        Self {
+            buffer: vec![[0.0; 2]; max_delay + 4],
+            write: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.fill([0.0; 2]);
+    }
+
+    /// Longest delay this can give, in samples
+    pub fn max_delay(&self) -> f32 {
+        (self.buffer.len() - 4) as f32
+    }
+
+    /// Sample from `back` samples ago, where 0 is the one just written
+    fn at(&self, back: usize) -> Sample {
+        let len = self.buffer.len();
+        self.buffer[(self.write + len - back % len) % len]
+    }
+
+    /// Push a sample in and get back the one from `delay` samples ago
+    pub fn tick(&mut self, s: Sample, delay: f32) -> Sample {
+        self.write = (self.write + 1) % self.buffer.len();
+        self.buffer[self.write] = s;
+
+        let delay = delay.clamp(0.0, self.max_delay());
+        let whole = delay as usize;
+        let frac = delay - whole as f32;
+
+        // NOTE(emily): The newest point we interpolate from would be one sample in the
+        // future when the delay is under a sample, so just use the newest we have.
+        let x0 = self.at(whole.saturating_sub(1));
+        let x1 = self.at(whole);
+        let x2 = self.at(whole + 1);
+        let x3 = self.at(whole + 2);
+
+        let mut out = [0.0; 2];
+        for (c, o) in out.iter_mut().enumerate() {
+            *o = hermite(x0[c], x1[c], x2[c], x3[c], frac);
+        }
+        out
+    }
+}
+
+/// 4 point, 3rd order Hermite interpolation between `x1` and `x2`
+pub fn hermite(x0: f32, x1: f32, x2: f32, x3: f32, t: f32) -> f32 {
+    let c1 = 0.5 * (x2 - x0);
+    let c2 = x0 - 2.5 * x1 + 2.0 * x2 - 0.5 * x3;
+    let c3 = 0.5 * (x3 - x0) + 1.5 * (x1 - x2);
+    ((c3 * t + c2) * t + c1) * t + x1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(len: usize) -> Vec<Sample> {
+        (0..len).map(|i| [i as f32, -(i as f32)]).collect()
+    }
+
+    #[test]
+    fn whole_samples_are_exact() {
+        let mut line = DelayLine::new();
+        let mut fractional = FractionalDelayLine::new(100);
+
+        let input = ramp(200);
+        for (i, &s) in input.iter().enumerate() {
+            let wanted = if i < 10 { [0.0; 2] } else { input[i - 10] };
+            assert_eq!(line.tick(s, 10), wanted);
+            assert_eq!(fractional.tick(s, 10.0), wanted);
+        }
+    }
+
+    #[test]
+    fn no_delay_is_no_delay() {
+        let mut fractional = FractionalDelayLine::new(100);
+        for s in ramp(10) {
+            assert_eq!(fractional.tick(s, 0.0), s);
+        }
+    }
+
+    #[test]
+    fn fractions_are_interpolated() {
+        let mut fractional = FractionalDelayLine::new(100);
+
+        // NOTE(emily): Cubic interpolation of a straight line lands exactly on it
+        for (i, s) in ramp(200).into_iter().enumerate() {
+            let out = fractional.tick(s, 2.25);
+            if i >= 4 {
+                assert!((out[0] - (i as f32 - 2.25)).abs() < 1e-3, "{:?}", out);
+                assert!((out[1] + (i as f32 - 2.25)).abs() < 1e-3, "{:?}", out);
+            }
+        }
+    }
+
+    #[test]
+    fn sweeping_doesnt_jump() {
+        let mut fractional = FractionalDelayLine::new(1000);
+        let mut last = None;
+
+        // A slow ramp read back through a delay that sweeps from 0 to 500 samples
+        for i in 0..2000 {
+            let s = [i as f32 * 0.01; 2];
+            let out = fractional.tick(s, (i as f32 * 0.25).min(500.0));
+            if let Some(last) = last {
+                let step: f32 = out[0] - last;
+                assert!(step.abs() <= 0.01 + 1e-4, "{} at {}", step, i);
+            }
+            last = Some(out[0]);
+        }
+    }
+
+    #[test]
+    fn delays_are_kept_to_the_line() {
+        let mut fractional = FractionalDelayLine::new(100);
+        let mut longest = FractionalDelayLine::new(100);
+        assert_eq!(fractional.max_delay(), 100.0);
+
+        for s in ramp(300) {
+            assert_eq!(fractional.tick(s, 1000.0), longest.tick(s, 100.0));
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use crate::Sample;
+
+use super::delay::hermite;
+
+/// How long the buffer fill is averaged over before we react to it
+const AVERAGE_SECS: f64 = 0.25;
+
+/// Roughly how long we take to pull the fill back to where it should be
+const CORRECTION_SECS: f64 = 1.0;
+
+/// How long the loop takes to learn a steady difference in clock rates. Four
+/// times [`CORRECTION_SECS`] keeps the loop from overshooting.
+const INTEGRAL_SECS: f64 = 4.0;
+
+/// Furthest we ever stray from reading at the rate we are sent, 2000ppm is
+/// a few cents of pitch at worst
+const MAX_DRIFT: f64 = 0.002;
+
+/// Input samples the resampler keeps hold of between blocks
+pub const HISTORY: usize = 4;
+
+/// How far behind its input the resampler runs, in samples
+pub const DELAY: usize = HISTORY - 1;
+
+/// Watches how full the buffer is, block after block, and works out how much
+/// faster or slower than normal we need to read to keep it where it should be.
+///
+/// When the sender and receiver share a clock the fill never moves, and the
+/// ratio stays at exactly 1.
+pub struct DriftTracker {
+    sample_rate: f64,
+    /// Average difference between how full the buffer is and how full it should be
+    average: f64,
+    /// Average error summed over time, in sample-seconds
+    integral: f64,
+    ratio: f64,
+}
+
+impl DriftTracker {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate: sample_rate.max(1) as f64,
+            average: 0.0,
+            integral: 0.0,
+            ratio: 1.0,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate.max(1) as f64;
+    }
+
+    pub fn reset(&mut self) {
+        self.average = 0.0;
+        self.integral = 0.0;
+        self.ratio = 1.0;
+    }
+
+    /// How many input samples to read per output sample
+    pub fn ratio(&self) -> f64 {
+        self.ratio
+    }
+
+    /// Take in how many more samples (or fewer, if negative) are waiting for
+    /// us than there should be, ahead of a block of `len` samples, and get
+    /// back the ratio to read that block at.
+    pub fn update(&mut self, error: f64, len: usize) -> f64 {
+        let elapsed = len as f64 / self.sample_rate;
+        self.average += (error - self.average) * (1.0 - (-elapsed / AVERAGE_SECS).exp());
+        self.integral += self.average * elapsed;
+
+        // NOTE(emily): Don't let the integral wind up past what we could ever act on
+        let limit = MAX_DRIFT * CORRECTION_SECS * self.sample_rate * INTEGRAL_SECS;
+        self.integral = self.integral.clamp(-limit, limit);
+
+        let correction =
+            (self.average + self.integral / INTEGRAL_SECS) / (CORRECTION_SECS * self.sample_rate);
+        self.ratio = 1.0 + correction.clamp(-MAX_DRIFT, MAX_DRIFT);
+        self.ratio
+    }
+}
+
+/// Reads its input at a ratio very close to 1, for soaking up clock drift.
+/// Interpolation is cubic, which is plenty when the ratio is this close to 1.
+///
+/// At a ratio of exactly 1 (and in step) this passes samples through
+/// untouched, [`DELAY`] samples late.
+pub struct DriftResampler {
+    history: [Sample; HISTORY],
+    /// Position of the next output sample, between `history[1]` and `history[2]`
+    phase: f64,
+}
+
+impl DriftResampler {
+    pub fn new() -> Self {
+        Self {
+            history: [[0.0; 2]; HISTORY],
+            phase: 0.0,
+        }
+    }
+
+    /// Start again from these samples, oldest first
+    pub fn reset(&mut self, history: &[Sample]) {
+        self.history.copy_from_slice(history);
+        self.phase = 0.0;
+    }
+
+    /// Number of input samples [`DriftResampler::process`] will want for `len` output samples
+    pub fn input_needed(&self, len: usize, ratio: f64) -> usize {
+        (self.phase + len as f64 * ratio) as usize
+    }
+
+    /// Fill `output` from `input`, which must be exactly as long as
+    /// [`DriftResampler::input_needed`] asked for.
+    pub fn process(&mut self, input: &[Sample], output: &mut [Sample], ratio: f64) {
+        let at = |i: usize| {
+            if i < HISTORY {
+                self.history[i]
+            } else {
+                input[i - HISTORY]
+            }
+        };
+
+        for (i, o) in output.iter_mut().enumerate() {
+            let position = self.phase + i as f64 * ratio;
+            let whole = position as usize;
+            let t = (position - whole as f64) as f32;
+
+            let (x0, x1, x2, x3) = (at(whole), at(whole + 1), at(whole + 2), at(whole + 3));
+            for c in 0..2 {
+                o[c] = hermite(x0[c], x1[c], x2[c], x3[c], t);
+            }
+        }
+
+        let end = self.phase + output.len() as f64 * ratio;
+        let consumed = end as usize;
+        let mut history = [[0.0; 2]; HISTORY];
+        for (i, h) in history.iter_mut().enumerate() {
+            *h = at(consumed + i);
+        }
+        self.history = history;
+        self.phase = end - consumed as f64;
+    }
+}
+
+impl Default for DriftResampler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+    const BLOCK: usize = 64;
+
+    #[test]
+    fn tracker_stays_at_unity_in_step() {
+        let mut tracker = DriftTracker::new(SAMPLE_RATE);
+        for _ in 0..10_000 {
+            assert_eq!(tracker.update(0.0, BLOCK), 1.0);
+        }
+    }
+
+    #[test]
+    fn tracker_never_strays_too_far() {
+        let mut tracker = DriftTracker::new(SAMPLE_RATE);
+        for _ in 0..10_000 {
+            tracker.update(1e6, BLOCK);
+        }
+        assert_eq!(tracker.ratio(), 1.0 + MAX_DRIFT);
+
+        for _ in 0..10_000 {
+            tracker.update(-1e6, BLOCK);
+        }
+        assert_eq!(tracker.ratio(), 1.0 - MAX_DRIFT);
+
+        tracker.reset();
+        assert_eq!(tracker.ratio(), 1.0);
+    }
+
+    #[test]
+    fn tracker_learns_a_steady_drift() {
+        // A sender whose clock runs 100ppm fast
+        let drift = 1.0001;
+        let mut tracker = DriftTracker::new(SAMPLE_RATE);
+        let mut error = 0.0;
+
+        // A minute of blocks
+        for _ in 0..SAMPLE_RATE as usize * 60 / BLOCK {
+            let ratio = tracker.update(error, BLOCK);
+            error += BLOCK as f64 * (drift - ratio);
+        }
+
+        assert!((tracker.ratio() - drift).abs() < 1e-6);
+        assert!(error.abs() < 1.0);
+    }
+
+    /// Input whose every sample is its own index, continuing on from `start`
+    fn ramp(start: usize, len: usize) -> Vec<Sample> {
+        (start..start + len)
+            .map(|i| [i as f32, -(i as f32)])
+            .collect()
+    }
+
+    #[test]
+    fn resampler_passes_through_at_unity() {
+        let mut resampler = DriftResampler::new();
+        let input = ramp(0, BLOCK * 4);
+
+        let mut output = vec![[0.0; 2]; input.len()];
+        for (i, o) in input.chunks(BLOCK).zip(output.chunks_mut(BLOCK)) {
+            assert_eq!(resampler.input_needed(BLOCK, 1.0), BLOCK);
+            resampler.process(i, o, 1.0);
+        }
+
+        assert!(output[..DELAY].iter().all(|s| *s == [0.0; 2]));
+        assert_eq!(output[DELAY..], input[..input.len() - DELAY]);
+    }
+
+    #[test]
+    fn resampler_follows_a_ratio_across_blocks() {
+        let ratio = 1.0 + MAX_DRIFT;
+        let mut resampler = DriftResampler::new();
+        resampler.reset(&ramp(0, HISTORY));
+
+        let mut next = HISTORY;
+        let mut written = 0;
+        for _ in 0..64 {
+            let needed = resampler.input_needed(BLOCK, ratio);
+            let mut output = vec![[0.0; 2]; BLOCK];
+            resampler.process(&ramp(next, needed), &mut output, ratio);
+            next += needed;
+
+            // NOTE(emily): Cubic interpolation gets a straight line exactly right, so
+            // every output lands on the ramp where it was read from
+            for (i, o) in output.iter().enumerate() {
+                let expected = 1.0 + (written + i) as f64 * ratio;
+                assert!((o[0] as f64 - expected).abs() < 1e-2);
+                assert!((o[1] as f64 + expected).abs() < 1e-2);
+            }
+            written += BLOCK;
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use crate::Sample;
+
+/// Anything below this is removed by the DC blocker
+const DC_CUTOFF_HZ: f32 = 10.0;
+
+/// How long the limiter takes to let go after it has clamped down
+const RELEASE_SECS: f32 = 0.05;
+
+/// Safety stage for everything we receive. A channel routed back on itself can
+/// run away, so this makes sure nothing non-finite, no DC and nothing above the
+/// ceiling ever reaches our output.
+pub struct SafetyLimiter {
+    /// Linear peak we never go above
+    ceiling: f32,
+    dc_coeff: f32,
+    dc_last_in: Sample,
+    dc_last_out: Sample,
+    release_coeff: f32,
+    gain: f32,
+    /// Whether we have had to step in since this was last cleared
+    pub engaged: bool,
+    /// Number of non-finite samples we have thrown away
+    pub scrubbed: usize,
+}
+
+impl SafetyLimiter {
+    pub fn new(ceiling: f32, sample_rate: u32) -> Self {
+        let mut limiter = Self {
+            ceiling,
+            dc_coeff: 0.0,
+            dc_last_in: [0.0; 2],
+            dc_last_out: [0.0; 2],
+            release_coeff: 0.0,
+            gain: 1.0,
+            engaged: false,
+            scrubbed: 0,
+        };
+        limiter.set_sample_rate(sample_rate);
+        limiter
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        let sample_rate = sample_rate.max(1) as f32;
+        self.dc_coeff = 1.0 - (std::f32::consts::TAU * DC_CUTOFF_HZ / sample_rate);
+        self.release_coeff = 1.0 - (-1.0 / (RELEASE_SECS * sample_rate)).exp();
+    }
+
+    pub fn set_ceiling(&mut self, ceiling: f32) {
+        self.ceiling = ceiling;
+    }
+
+    pub fn reset(&mut self) {
+        self.dc_last_in = [0.0; 2];
+        self.dc_last_out = [0.0; 2];
+        self.gain = 1.0;
+    }
+
+    pub fn process(&mut self, output: &mut [Sample]) {
+        for s in output.iter_mut() {
+            if !(s[0].is_finite() && s[1].is_finite()) {
+                // NOTE(emily): Once something is non-finite everything downstream of it will
+                // be too, so start the filters again from silence.
+                *s = [0.0; 2];
+                self.scrubbed += 1;
+                self.engaged = true;
+                self.reset();
+            }
+
+            let mut out = [0.0; 2];
+            for c in 0..2 {
+                out[c] = s[c] - self.dc_last_in[c] + self.dc_coeff * self.dc_last_out[c];
+                self.dc_last_in[c] = s[c];
+                self.dc_last_out[c] = out[c];
+            }
+
+            // Huge but finite input can still overflow the filter
+            if !(out[0].is_finite() && out[1].is_finite()) {
+                out = [0.0; 2];
+                self.engaged = true;
+                self.reset();
+            }
+
+            let peak = out[0].abs().max(out[1].abs());
+            let wanted = if peak > self.ceiling {
+                self.ceiling / peak
+            } else {
+                1.0
+            };
+
+            // Clamp down instantly, let go slowly
+            if wanted < self.gain {
+                self.gain = wanted;
+                self.engaged = true;
+            } else {
+                self.gain += (wanted - self.gain) * self.release_coeff;
+            }
+
+            *s = [
+                (out[0] * self.gain).clamp(-self.ceiling, self.ceiling),
+                (out[1] * self.gain).clamp(-self.ceiling, self.ceiling),
+            ];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    fn sine(amplitude: f32, len: usize) -> Vec<Sample> {
+        (0..len)
+            .map(|i| {
+                let s = (std::f32::consts::TAU * 1000.0 * i as f32 / SAMPLE_RATE as f32).sin();
+                [s * amplitude, -s * amplitude]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn non_finite_samples_are_scrubbed() {
+        let mut limiter = SafetyLimiter::new(1.0, SAMPLE_RATE);
+        let mut output = sine(0.5, 64);
+        output[10] = [f32::NAN, 0.5];
+        output[20] = [f32::INFINITY, f32::NEG_INFINITY];
+        limiter.process(&mut output);
+
+        assert!(output.iter().flatten().all(|s| s.is_finite()));
+        assert_eq!(output[10], [0.0; 2]);
+        assert_eq!(limiter.scrubbed, 2);
+        assert!(limiter.engaged);
+    }
+
+    #[test]
+    fn huge_samples_dont_overflow() {
+        let mut limiter = SafetyLimiter::new(1.0, SAMPLE_RATE);
+        let mut output = vec![[f32::MAX; 2], [-f32::MAX; 2], [f32::MAX; 2]];
+        limiter.process(&mut output);
+
+        assert!(output.iter().flatten().all(|s| s.abs() <= 1.0));
+        assert_eq!(limiter.scrubbed, 0);
+    }
+
+    #[test]
+    fn nothing_gets_past_the_ceiling() {
+        let ceiling = 0.5;
+        let mut limiter = SafetyLimiter::new(ceiling, SAMPLE_RATE);
+        let mut output = sine(10.0, SAMPLE_RATE as usize);
+        limiter.process(&mut output);
+
+        assert!(output.iter().flatten().all(|s| s.abs() <= ceiling));
+        assert!(limiter.engaged);
+
+        // Clamped down rather than clipped, so it still has its shape
+        let peak = output[SAMPLE_RATE as usize / 2..]
+            .iter()
+            .map(|s| s[0].abs())
+            .fold(0.0, f32::max);
+        assert!(peak > ceiling * 0.9, "{}", peak);
+    }
+
+    #[test]
+    fn quiet_signals_go_through() {
+        let mut limiter = SafetyLimiter::new(0.5, SAMPLE_RATE);
+        let input = sine(0.25, SAMPLE_RATE as usize);
+        let mut output = input.clone();
+        limiter.process(&mut output);
+
+        assert!(!limiter.engaged);
+        for (o, i) in output.iter().zip(&input).skip(SAMPLE_RATE as usize / 10) {
+            assert!((o[0] - i[0]).abs() < 0.01 && (o[1] - i[1]).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn dc_is_removed() {
+        let mut limiter = SafetyLimiter::new(1.0, SAMPLE_RATE);
+        let mut output = vec![[0.5, -0.5]; SAMPLE_RATE as usize];
+        limiter.process(&mut output);
+
+        let last = output.last().unwrap();
+        assert!(last[0].abs() < 0.001 && last[1].abs() < 0.001, "{:?}", last);
+    }
+}
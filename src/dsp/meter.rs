@@ -0,0 +1,141 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use crate::Sample;
+
+/// How long a peak takes to fall away by a factor of e
+const PEAK_RELEASE_SECS: f32 = 0.3;
+
+/// Roughly how much history RMS is averaged over
+const RMS_WINDOW_SECS: f32 = 0.3;
+
+/// Number of samples the scope shows
+pub const SCOPE_LEN: usize = 1024;
+
+/// Levels of a signal as linear amplitudes
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Reading {
+    pub peak: f32,
+    pub rms: f32,
+}
+
+/// The latest [`Reading`] of a signal. Written from the audio thread and read
+/// from the editor without either of them waiting on the other.
+#[derive(Debug, Default)]
+pub struct Levels {
+    peak: AtomicU32,
+    rms: AtomicU32,
+}
+
+impl Levels {
+    pub fn read(&self) -> Reading {
+        Reading {
+            peak: f32::from_bits(self.peak.load(Ordering::Relaxed)),
+            rms: f32::from_bits(self.rms.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn store(&self, reading: Reading) {
+        self.peak.store(reading.peak.to_bits(), Ordering::Relaxed);
+        self.rms.store(reading.rms.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// The last [`SCOPE_LEN`] samples of a signal, mixed down to mono. Only one
+/// thread may write to it, any number may read.
+#[derive(Debug)]
+pub struct Scope {
+    samples: [AtomicU32; SCOPE_LEN],
+    /// Where the next sample goes
+    position: AtomicUsize,
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Self {
+            samples: std::array::from_fn(|_| AtomicU32::new(0)),
+            position: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Scope {
+    pub fn write(&self, samples: &[Sample]) {
+        let mut position = self.position.load(Ordering::Relaxed);
+        for s in samples {
+            self.samples[position % SCOPE_LEN]
+                .store(((s[0] + s[1]) * 0.5).to_bits(), Ordering::Relaxed);
+            position = position.wrapping_add(1);
+        }
+        self.position.store(position, Ordering::Release);
+    }
+
+    /// Copy out everything the scope holds, oldest first
+    pub fn read(&self, output: &mut Vec<f32>) {
+        // NOTE(emily): This can tear if the audio thread writes while we read, which
+        // for something that is only ever looked at is fine.
+        let position = self.position.load(Ordering::Acquire);
+        output.clear();
+        output.extend((0..SCOPE_LEN).map(|i| {
+            f32::from_bits(
+                self.samples[position.wrapping_add(i) % SCOPE_LEN].load(Ordering::Relaxed),
+            )
+        }));
+    }
+}
+
+/// Everything the editor shows about our audio as it happens
+#[derive(Debug, Default)]
+pub struct Meters {
+    /// Our input
+    pub input: Levels,
+    /// What we send to, or receive from, our channel
+    pub channel: Levels,
+    /// Same as `channel`, sample by sample
+    pub scope: Scope,
+}
+
+/// Follows the peak and RMS level of a signal on the audio thread
+pub struct Meter {
+    reading: Reading,
+    mean_square: f32,
+    peak_coeff: f32,
+    rms_coeff: f32,
+}
+
+impl Meter {
+    pub fn new(sample_rate: u32) -> Self {
+        let mut meter = Self {
+            reading: Reading::default(),
+            mean_square: 0.0,
+            peak_coeff: 0.0,
+            rms_coeff: 0.0,
+        };
+        meter.set_sample_rate(sample_rate);
+        meter
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        let sample_rate = sample_rate.max(1) as f32;
+        self.peak_coeff = (-1.0 / (PEAK_RELEASE_SECS * sample_rate)).exp();
+        self.rms_coeff = 1.0 - (-1.0 / (RMS_WINDOW_SECS * sample_rate)).exp();
+    }
+
+    /// Take in a block and publish where the levels are now
+    pub fn process(&mut self, samples: &[Sample], levels: &Levels) {
+        for s in samples {
+            // Don't let one bad sample stick the meter forever
+            if !(s[0].is_finite() && s[1].is_finite()) {
+                continue;
+            }
+
+            let peak = s[0].abs().max(s[1].abs());
+            self.reading.peak = peak.max(self.reading.peak * self.peak_coeff);
+
+            let square = (s[0] * s[0] + s[1] * s[1]) * 0.5;
+            self.mean_square += (square - self.mean_square) * self.rms_coeff;
+        }
+
+        self.reading.rms = self.mean_square.sqrt();
+        levels.store(self.reading);
+    }
+}
@@ -0,0 +1,266 @@
+use crate::Sample;
+
+use super::drift::{self, DriftResampler};
+
+/// Length of the sinc kernel, in input samples
+const TAPS: usize = 64;
+
+/// Number of fractional positions the kernel is worked out for, anything in
+/// between is interpolated
+const PHASES: usize = 512;
+
+/// How much of the band below Nyquist we keep. Rolling off a little early
+/// leaves the kernel room to get all the way down before anything can alias.
+const PASSBAND: f64 = 0.92;
+
+/// Band-limited resampler for when our channel is sent at a different rate to
+/// the one we run at. Windowed sinc, with the cutoff set for whichever of the
+/// two rates is lower.
+pub struct SincResampler {
+    /// `TAPS` coefficients for each of `PHASES + 1` fractional positions
+    kernel: Vec<f32>,
+    history: [Sample; TAPS],
+    /// Position of the next output sample, relative to the start of `history`
+    phase: f64,
+}
+
+/// 4 term Blackman-Harris window, `x` runs from -1 to 1
+fn window(x: f64) -> f64 {
+    use std::f64::consts::PI;
+    0.35875
+        + 0.48829 * (PI * x).cos()
+        + 0.14128 * (2.0 * PI * x).cos()
+        + 0.01168 * (3.0 * PI * x).cos()
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = std::f64::consts::PI * x;
+        x.sin() / x
+    }
+}
+
+impl SincResampler {
+    /// Kernel centre sits between these two taps
+    const CENTRE: usize = TAPS / 2 - 1;
+
+    /// Input samples kept hold of between blocks
+    pub const HISTORY: usize = TAPS;
+
+    /// How far behind its input this runs, in input samples
+    pub const DELAY: usize = TAPS - Self::CENTRE;
+
+    /// Make a resampler for reading `ratio` input samples per output sample
+    pub fn new(ratio: f64) -> Self {
+        let cutoff = PASSBAND * (1.0 / ratio).min(1.0);
+        let half_width = (TAPS / 2) as f64;
+
+        let mut kernel = vec![0.0; (PHASES + 1) * TAPS];
+        for (phase, taps) in kernel.chunks_mut(TAPS).enumerate() {
+            let t = phase as f64 / PHASES as f64;
+
+            let mut sum = 0.0;
+            let mut coefficients = [0.0; TAPS];
+            for (j, c) in coefficients.iter_mut().enumerate() {
+                let x = j as f64 - Self::CENTRE as f64 - t;
+                *c = cutoff * sinc(cutoff * x) * window((x / half_width).clamp(-1.0, 1.0));
+                sum += *c;
+            }
+
+            // NOTE(emily): Normalise every phase so that DC comes through at exactly unity
+            for (tap, c) in taps.iter_mut().zip(coefficients) {
+                *tap = (c / sum) as f32;
+            }
+        }
+
+        Self {
+            kernel,
+            history: [[0.0; 2]; TAPS],
+            phase: 0.0,
+        }
+    }
+
+    /// Start again from these samples, oldest first
+    pub fn reset(&mut self, history: &[Sample]) {
+        self.history.copy_from_slice(history);
+        self.phase = 0.0;
+    }
+
+    /// Number of input samples [`SincResampler::process`] will want for `len` output samples
+    pub fn input_needed(&self, len: usize, ratio: f64) -> usize {
+        (self.phase + len as f64 * ratio) as usize
+    }
+
+    /// Fill `output` from `input`, which must be exactly as long as
+    /// [`SincResampler::input_needed`] asked for.
+    pub fn process(&mut self, input: &[Sample], output: &mut [Sample], ratio: f64) {
+        let at = |i: usize| {
+            if i < Self::HISTORY {
+                self.history[i]
+            } else {
+                input[i - Self::HISTORY]
+            }
+        };
+
+        for (i, o) in output.iter_mut().enumerate() {
+            let position = self.phase + i as f64 * ratio;
+            let whole = position as usize;
+
+            // Blend between the two nearest phases we worked out
+            let phase = (position - whole as f64) * PHASES as f64;
+            let index = (phase as usize).min(PHASES - 1);
+            let blend = (phase - index as f64) as f32;
+            let (a, b) = (
+                &self.kernel[index * TAPS..(index + 1) * TAPS],
+                &self.kernel[(index + 1) * TAPS..(index + 2) * TAPS],
+            );
+
+            let mut out = [0.0; 2];
+            for j in 0..TAPS {
+                let s = at(whole + j);
+                let c = a[j] + (b[j] - a[j]) * blend;
+                out[0] += s[0] * c;
+                out[1] += s[1] * c;
+            }
+            *o = out;
+        }
+
+        let end = self.phase + output.len() as f64 * ratio;
+        let consumed = end as usize;
+        let mut history = [[0.0; 2]; TAPS];
+        for (i, h) in history.iter_mut().enumerate() {
+            *h = at(consumed + i);
+        }
+        self.history = history;
+        self.phase = end - consumed as f64;
+    }
+}
+
+/// Whichever resampler suits the rate our channel is sent at
+pub enum Resampler {
+    /// Same rate as us, only ever a hair off for drift
+    Drift(DriftResampler),
+    Sinc(Box<SincResampler>),
+}
+
+impl Resampler {
+    /// Make a resampler for reading a channel sent at `source_rate` when we run at `sample_rate`
+    pub fn new(source_rate: u32, sample_rate: u32) -> Self {
+        if source_rate == sample_rate {
+            Resampler::Drift(DriftResampler::new())
+        } else {
+            Resampler::Sinc(Box::new(SincResampler::new(
+                source_rate as f64 / sample_rate.max(1) as f64,
+            )))
+        }
+    }
+
+    /// Input samples that need to be handed to [`Resampler::reset`]
+    pub fn history_len(&self) -> usize {
+        match self {
+            Resampler::Drift(_) => drift::HISTORY,
+            Resampler::Sinc(_) => SincResampler::HISTORY,
+        }
+    }
+
+    /// How far behind its input this runs, in input samples
+    pub fn delay(&self) -> usize {
+        match self {
+            Resampler::Drift(_) => drift::DELAY,
+            Resampler::Sinc(_) => SincResampler::DELAY,
+        }
+    }
+
+    /// Start again from these samples, oldest first
+    pub fn reset(&mut self, history: &[Sample]) {
+        match self {
+            Resampler::Drift(r) => r.reset(history),
+            Resampler::Sinc(r) => r.reset(history),
+        }
+    }
+
+    pub fn input_needed(&self, len: usize, ratio: f64) -> usize {
+        match self {
+            Resampler::Drift(r) => r.input_needed(len, ratio),
+            Resampler::Sinc(r) => r.input_needed(len, ratio),
+        }
+    }
+
+    pub fn process(&mut self, input: &[Sample], output: &mut [Sample], ratio: f64) {
+        match self {
+            Resampler::Drift(r) => r.process(input, output, ratio),
+            Resampler::Sinc(r) => r.process(input, output, ratio),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK: usize = 64;
+
+    /// Run `input` through a resampler a block at a time, returning what came out
+    /// and how much input it took
+    fn run(
+        resampler: &mut SincResampler,
+        input: &[Sample],
+        ratio: f64,
+        blocks: usize,
+    ) -> (Vec<Sample>, usize) {
+        let mut output = vec![[0.0; 2]; BLOCK * blocks];
+        let mut next = 0;
+        for block in output.chunks_mut(BLOCK) {
+            let needed = resampler.input_needed(BLOCK, ratio);
+            resampler.process(&input[next..next + needed], block, ratio);
+            next += needed;
+        }
+        (output, next)
+    }
+
+    #[test]
+    fn sinc_runs_delay_samples_behind() {
+        let mut resampler = SincResampler::new(1.0);
+        let mut input = vec![[0.0; 2]; BLOCK * 4];
+        input[10] = [1.0, -1.0];
+
+        let (output, _) = run(&mut resampler, &input, 1.0, 4);
+        let peak = output
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1[0].total_cmp(&b.1[0]))
+            .unwrap();
+        assert_eq!(peak.0, 10 + SincResampler::DELAY);
+        assert!(peak.1[0] > 0.9 && peak.1[1] < -0.9);
+    }
+
+    #[test]
+    fn sinc_passes_dc_at_unity() {
+        for ratio in [48000.0 / 44100.0, 44100.0 / 48000.0, 0.5, 4.0] {
+            let mut resampler = SincResampler::new(ratio);
+            resampler.reset(&[[1.0, -0.5]; SincResampler::HISTORY]);
+
+            let input = vec![[1.0, -0.5]; BLOCK * 64];
+            let (output, _) = run(&mut resampler, &input, ratio, 8);
+            for s in output {
+                assert!((s[0] - 1.0).abs() < 1e-4, "{} {:?}", ratio, s);
+                assert!((s[1] + 0.5).abs() < 1e-4, "{} {:?}", ratio, s);
+            }
+        }
+    }
+
+    #[test]
+    fn sinc_takes_ratio_times_as_much_input() {
+        for ratio in [48000.0 / 44100.0, 44100.0 / 96000.0, 3.0] {
+            let mut resampler = SincResampler::new(ratio);
+            let input = vec![[0.0; 2]; BLOCK * 256];
+
+            // NOTE(emily): Only ever a fraction of a sample is left over between blocks
+            let (_, taken) = run(&mut resampler, &input, ratio, 64);
+            let exact = (BLOCK * 64) as f64 * ratio;
+            assert!((taken as f64 - exact).abs() < 1.0, "{} {}", taken, exact);
+        }
+    }
+}
@@ -0,0 +1,46 @@
+/// How long it takes a smoothed value to reach a new target
+const SMOOTHING_TIME_SECS: f32 = 0.02;
+
+/// A value that ramps linearly towards its target rather than jumping, to
+/// avoid zipper noise when parameters change.
+pub struct Smoothed {
+    current: f32,
+    target: f32,
+    step: f32,
+    remaining: usize,
+}
+
+impl Smoothed {
+    pub fn new(value: f32) -> Self {
+        Self {
+            current: value,
+            target: value,
+            step: 0.0,
+            remaining: 0,
+        }
+    }
+
+    pub fn set_target(&mut self, target: f32, sample_rate: u32) {
+        if target == self.target {
+            return;
+        }
+
+        let len = ((sample_rate as f32 * SMOOTHING_TIME_SECS) as usize).max(1);
+        self.target = target;
+        self.step = (target - self.current) / len as f32;
+        self.remaining = len;
+    }
+
+    pub fn tick(&mut self) -> f32 {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.current = if self.remaining == 0 {
+                self.target
+            } else {
+                self.current + self.step
+            };
+        }
+
+        self.current
+    }
+}
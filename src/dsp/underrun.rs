@@ -0,0 +1,198 @@
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+
+use crate::Sample;
+
+/// Length of the fades in and out of an underrun, in samples
+const FADE_LEN: usize = 128;
+
+/// Largest block we keep around to hold
+const MAX_BLOCK: usize = 8192;
+
+/// What to play while we have nothing to play
+#[derive(Debug, PartialEq, Display, Clone, Copy, Eq, Serialize, Deserialize)]
+pub enum UnderrunBehaviour {
+    /// Fade out to silence
+    Mute,
+    /// Keep repeating the last block we had
+    Hold,
+}
+
+impl UnderrunBehaviour {
+    pub const ALL: [UnderrunBehaviour; 2] = [UnderrunBehaviour::Mute, UnderrunBehaviour::Hold];
+}
+
+/// Smooths over gaps in the received signal, so that running out of samples
+/// doesn't leak garbage or click.
+pub struct UnderrunGuard {
+    pub behaviour: UnderrunBehaviour,
+    /// Last good block we received
+    held: Vec<Sample>,
+    /// Where we are in `held` while repeating it
+    held_pos: usize,
+    /// Gain applied to `held` as it fades out
+    held_gain: f32,
+    underrunning: bool,
+    /// Number of times we have run out of samples
+    pub underruns: usize,
+}
+
+impl UnderrunGuard {
+    pub fn new(behaviour: UnderrunBehaviour) -> Self {
+        Self {
+            behaviour,
+            held: Vec::with_capacity(MAX_BLOCK),
+            held_pos: 0,
+            held_gain: 0.0,
+            underrunning: false,
+            underruns: 0,
+        }
+    }
+
+    /// Forget whatever we were holding, e.g. because we changed channel
+    pub fn reset(&mut self) {
+        self.held.clear();
+        self.held_pos = 0;
+        self.held_gain = 0.0;
+        self.underrunning = false;
+    }
+
+    fn next_held(&mut self) -> Sample {
+        if self.held.is_empty() {
+            return [0.0; 2];
+        }
+
+        let s = self.held[self.held_pos];
+        self.held_pos = (self.held_pos + 1) % self.held.len();
+        [s[0] * self.held_gain, s[1] * self.held_gain]
+    }
+
+    /// `output` holds a freshly received block. If we are coming out of an underrun
+    /// then crossfade from whatever we were playing into it.
+    pub fn received(&mut self, output: &mut [Sample]) {
+        if self.underrunning {
+            self.underrunning = false;
+
+            let len = FADE_LEN.min(output.len());
+            for (i, s) in output[..len].iter_mut().enumerate() {
+                let t = (i + 1) as f32 / len as f32;
+                let held = self.next_held();
+                s[0] = held[0] * (1.0 - t) + s[0] * t;
+                s[1] = held[1] * (1.0 - t) + s[1] * t;
+            }
+        }
+
+        // NOTE(emily): `held` never grows past its initial capacity so this doesn't allocate
+        let len = output.len().min(self.held.capacity());
+        self.held.clear();
+        self.held.extend_from_slice(&output[..len]);
+        self.held_pos = 0;
+        self.held_gain = 1.0;
+    }
+
+    /// We have nothing to put in `output`, fill it with something sensible
+    pub fn underrun(&mut self, output: &mut [Sample]) {
+        if !self.underrunning {
+            self.underrunning = true;
+            self.underruns += 1;
+        }
+
+        for s in output.iter_mut() {
+            if self.behaviour == UnderrunBehaviour::Mute {
+                self.held_gain = (self.held_gain - 1.0 / FADE_LEN as f32).max(0.0);
+            }
+            *s = self.next_held();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(len: usize) -> Vec<Sample> {
+        (0..len).map(|i| [i as f32, -(i as f32)]).collect()
+    }
+
+    #[test]
+    fn mute_fades_out_to_silence() {
+        let mut guard = UnderrunGuard::new(UnderrunBehaviour::Mute);
+        guard.received(&mut vec![[1.0; 2]; 256]);
+
+        let mut output = vec![[f32::NAN; 2]; 256];
+        guard.underrun(&mut output);
+
+        assert_eq!(output[0], [1.0 - 1.0 / FADE_LEN as f32; 2]);
+        assert!(output.windows(2).all(|w| w[1][0] <= w[0][0]));
+        assert!(output[FADE_LEN - 1..].iter().all(|s| *s == [0.0; 2]));
+        assert_eq!(guard.underruns, 1);
+
+        // Still the same underrun
+        guard.underrun(&mut output);
+        assert!(output.iter().all(|s| *s == [0.0; 2]));
+        assert_eq!(guard.underruns, 1);
+    }
+
+    #[test]
+    fn hold_repeats_the_last_block() {
+        let mut guard = UnderrunGuard::new(UnderrunBehaviour::Hold);
+        let block = ramp(64);
+        guard.received(&mut block.clone());
+
+        let mut output = vec![[0.0; 2]; 160];
+        guard.underrun(&mut output);
+
+        for (i, s) in output.iter().enumerate() {
+            assert_eq!(*s, block[i % block.len()]);
+        }
+    }
+
+    #[test]
+    fn nothing_to_hold_is_silence() {
+        let mut guard = UnderrunGuard::new(UnderrunBehaviour::Hold);
+        let mut output = vec![[1.0; 2]; 64];
+        guard.underrun(&mut output);
+        assert!(output.iter().all(|s| *s == [0.0; 2]));
+
+        guard.received(&mut vec![[1.0; 2]; 64]);
+        guard.reset();
+        guard.underrun(&mut output);
+        assert!(output.iter().all(|s| *s == [0.0; 2]));
+    }
+
+    #[test]
+    fn crossfades_back_from_what_was_held() {
+        let mut guard = UnderrunGuard::new(UnderrunBehaviour::Hold);
+        guard.received(&mut vec![[1.0; 2]; 256]);
+        guard.underrun(&mut vec![[0.0; 2]; 64]);
+
+        let mut output = vec![[0.0; 2]; 256];
+        guard.received(&mut output);
+
+        for (i, s) in output[..FADE_LEN].iter().enumerate() {
+            let t = (i + 1) as f32 / FADE_LEN as f32;
+            assert_eq!(*s, [1.0 - t; 2]);
+        }
+        assert!(output[FADE_LEN..].iter().all(|s| *s == [0.0; 2]));
+
+        // Back to normal until the next underrun
+        let mut output = ramp(64);
+        guard.received(&mut output);
+        assert_eq!(output, ramp(64));
+    }
+
+    #[test]
+    fn fades_in_after_muting() {
+        let mut guard = UnderrunGuard::new(UnderrunBehaviour::Mute);
+        guard.received(&mut vec![[1.0; 2]; 64]);
+        guard.underrun(&mut vec![[0.0; 2]; 256]);
+
+        let mut output = vec![[1.0; 2]; 256];
+        guard.received(&mut output);
+
+        for (i, s) in output[..FADE_LEN].iter().enumerate() {
+            assert_eq!(*s, [(i + 1) as f32 / FADE_LEN as f32; 2]);
+        }
+        assert!(output[FADE_LEN..].iter().all(|s| *s == [1.0; 2]));
+    }
+}
@@ -0,0 +1,1188 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use uuid::Uuid;
+
+use crate::{
+    dsp::{
+        delay::{DelayLine, FractionalDelayLine},
+        drift::DriftTracker,
+        limiter::SafetyLimiter,
+        meter::{Meter, Meters},
+        resample::Resampler,
+        smooth::Smoothed,
+        underrun::{UnderrunBehaviour, UnderrunGuard},
+    },
+    layout::{self, Frame, Layout, Mixer, Port, MAX_WIDTH},
+    message::{PluginMessage, PluginStateChange},
+    params::{Param, Params, MAX_DELAY_SAMPLES},
+    router::{ChannelReceiver, ChannelSender, InstanceInfo, Located, SharedRouter, Timing},
+    state::{InstanceState, Preset},
+    AlignmentMode, Mode, Sample, DEFAULT_LATENCY,
+};
+
+/// How often we look for channels that nobody is using any more
+const GC_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long a channel can go without anyone attached to it before it is deleted
+const UNUSED_CHANNEL_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// How long the UI keeps showing that the safety limiter stepped in
+const PROTECTION_HOLD: Duration = Duration::from_secs(1);
+
+/// How often the UI hears about our channel's statistics
+const STATS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often we remind other instances that we are still here
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How far the host's idea of where its song is can stray from our own count
+/// before we take it that the song was seeked, in milliseconds
+const SONG_POSITION_SLACK_MS: u64 = 1;
+
+/// Largest block we process in one go when we need a scratch buffer
+const MAX_BLOCK: usize = 8192;
+
+/// Everything the engine needs from whatever is running it, be that a plugin
+/// host and its editor, or a test.
+pub trait Host {
+    fn log(&self, msg: String);
+
+    /// Tell the host how much latency we add, for delay compensation
+    fn set_latency(&self, latency: u32);
+
+    /// A parameter was changed by something other than the host, so that it can
+    /// record automation
+    fn parameter_changed(&self, param: Param, value: f32);
+
+    /// Let the UI know something changed
+    fn notify(&self, change: PluginStateChange);
+
+    /// Where the host has put us, e.g. the name of our mixer track
+    fn location(&self) -> Option<String> {
+        None
+    }
+
+    /// Same as [`Host::notify`] but safe to call from the audio thread, at the
+    /// cost of maybe dropping the change if the UI is behind.
+    fn try_notify(&self, change: PluginStateChange) {
+        self.notify(change)
+    }
+
+    /// Microseconds on a clock every instance shares, which is how receivers tell
+    /// which blocks were rendered in the same block period as them
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0)
+    }
+}
+
+/// All of the routing and processing for one instance, without anything that
+/// ties it to a particular host.
+pub struct Engine<H: Host> {
+    host: H,
+    /// Who we are to other instances
+    instance: Uuid,
+    label: String,
+    mode: Mode,
+    uuid: Option<Uuid>,
+    /// Where our stereo sits on our channel
+    port: Port,
+    /// Layout of our channel that `mixer` is set up for
+    mixer_layout: Layout,
+    /// Gets our stereo onto our channel when sending, and back off it when receiving
+    mixer: Mixer,
+    receiver: Option<ChannelReceiver>,
+    sender: Option<ChannelSender>,
+    /// How far behind the senders we read, in samples
+    latency: usize,
+    /// Whether our receiver is sitting `latency` behind the senders
+    in_sync: bool,
+    alignment: AlignmentMode,
+    sample_rate: u32,
+    /// Keeps us reading at the rate we are sent, even when that isn't quite our sample rate
+    drift: DriftTracker,
+    resampler: Resampler,
+    /// Rate our channel is sent at, which `resampler` is set up for
+    source_rate: u32,
+    underrun: UnderrunGuard,
+    logged_underruns: usize,
+    /// Keeps our input in line with what we receive in return mode
+    dry_delay: DelayLine,
+    params: Params,
+    send_gain: Smoothed,
+    receive_gain: Smoothed,
+    /// How much of what we receive to mix with our input in return mode
+    mix: Smoothed,
+    /// Somewhere to put our input after applying gain to it, before sending it,
+    /// or what we read from our channel before resampling it
+    scratch: Vec<Sample>,
+    /// What we send to, or read from, our channel, in its layout
+    frames: Vec<Frame>,
+    /// Deliberate delay on what we receive, for shaping feedback
+    loop_delay: FractionalDelayLine,
+    /// Length of `loop_delay` in samples
+    loop_delay_time: Smoothed,
+    /// Host tempo in beats per minute
+    tempo: f32,
+    /// Samples we have rendered since we were made
+    sample_counter: u64,
+    /// Where the host's song is at the start of the next block, if it is playing
+    song_position: Option<u64>,
+    /// Keeps a runaway loop from reaching our output
+    limiter: SafetyLimiter,
+    /// When the limiter last had to step in
+    last_protection: Option<Instant>,
+    reported_protection: bool,
+    logged_scrubbed: usize,
+    input_meter: Meter,
+    channel_meter: Meter,
+    /// Where the editor picks up our levels from
+    meters: Arc<Meters>,
+    last_gc: Instant,
+    last_stats: Instant,
+    last_announce: Instant,
+    router: SharedRouter,
+}
+
+impl<H: Host> Engine<H> {
+    pub fn new(host: H, router: SharedRouter) -> Self {
+        let mut engine = Self {
+            host,
+            instance: Uuid::new_v4(),
+            label: String::new(),
+            mode: Mode::Receiver,
+            uuid: None,
+            port: Port::Mix,
+            mixer_layout: Layout::Stereo,
+            mixer: Mixer::receiving(Port::Mix, Layout::Stereo),
+            receiver: None,
+            sender: None,
+            latency: DEFAULT_LATENCY,
+            in_sync: false,
+            alignment: AlignmentMode::Queue,
+            sample_rate: 44100,
+            drift: DriftTracker::new(44100),
+            resampler: Resampler::new(44100, 44100),
+            source_rate: 44100,
+            underrun: UnderrunGuard::new(UnderrunBehaviour::Mute),
+            logged_underruns: 0,
+            dry_delay: DelayLine::new(),
+            params: Params::default(),
+            send_gain: Smoothed::new(Params::default().routed_gain(Param::SendGain)),
+            receive_gain: Smoothed::new(Params::default().routed_gain(Param::ReceiveGain)),
+            mix: Smoothed::new(Params::default().get(Param::Mix)),
+            scratch: vec![[0.0; 2]; MAX_BLOCK],
+            frames: vec![[0.0; MAX_WIDTH]; MAX_BLOCK],
+            loop_delay: FractionalDelayLine::new(MAX_DELAY_SAMPLES),
+            loop_delay_time: Smoothed::new(0.0),
+            tempo: 120.0,
+            sample_counter: 0,
+            song_position: None,
+            limiter: SafetyLimiter::new(Params::default().ceiling(), 44100),
+            last_protection: None,
+            reported_protection: false,
+            logged_scrubbed: 0,
+            input_meter: Meter::new(44100),
+            channel_meter: Meter::new(44100),
+            meters: Arc::new(Meters::default()),
+            last_gc: Instant::now(),
+            last_stats: Instant::now(),
+            last_announce: Instant::now(),
+            router,
+        };
+        engine.announce();
+        engine
+    }
+
+    pub fn host(&self) -> &H {
+        &self.host
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn channel(&self) -> Option<Uuid> {
+        self.uuid
+    }
+
+    pub fn port(&self) -> Port {
+        self.port
+    }
+
+    pub fn instance(&self) -> Uuid {
+        self.instance
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// How much faster (or slower, if negative) than our sample rate we are
+    /// reading our channel, in parts per million
+    pub fn drift_ppm(&self) -> f64 {
+        (self.drift.ratio() - 1.0) * 1e6
+    }
+
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    pub fn meters(&self) -> Arc<Meters> {
+        self.meters.clone()
+    }
+
+    pub fn set_channel(&mut self, uuid: Uuid) {
+        // Leave our old channel
+        // Set our id and join the new channel
+        self.detach();
+
+        self.uuid = Some(uuid);
+        self.attach();
+
+        // Inform UI of this
+        self.send_channel_id();
+        self.announce();
+    }
+
+    /// Stop sending or receiving altogether
+    pub fn leave_channel(&mut self) {
+        self.detach();
+        self.uuid = None;
+        self.send_channel_id();
+        self.announce();
+    }
+
+    pub fn set_port(&mut self, port: Port) {
+        self.port = port;
+        self.update_mixer(self.mixer_layout);
+        self.send_port();
+    }
+
+    /// Set up to mix to or from a channel with this layout
+    fn update_mixer(&mut self, layout: Layout) {
+        self.mixer_layout = layout;
+        self.mixer = if self.mode.is_receiving() {
+            Mixer::receiving(self.port, layout)
+        } else {
+            Mixer::sending(self.port, layout)
+        };
+    }
+
+    pub fn set_label(&mut self, label: String) {
+        self.label = label;
+        self.send_label();
+        self.announce();
+    }
+
+    /// Let other instances know who we are and what we are connected to
+    fn announce(&mut self) {
+        self.last_announce = Instant::now();
+
+        // NOTE(emily): Not being listed only means that nobody else can see us, routing carries on
+        // NOTE(emily): Until the user names us, go by wherever the host put us, which
+        // is worked out afresh every time so that it follows us if we are moved.
+        let label = if self.label.is_empty() {
+            self.host.location().unwrap_or_default()
+        } else {
+            self.label.clone()
+        };
+
+        self.router.announce(&InstanceInfo {
+            id: self.instance,
+            label,
+            mode: self.mode,
+            channel: self.uuid,
+            process: std::process::id(),
+        });
+    }
+
+    /// Hold on to our place on our channel. If we go quiet for long enough, e.g.
+    /// because the host stopped calling us, it is given away. Rendering does this
+    /// every block, this is for while the host isn't rendering us.
+    fn keep_alive(&self) {
+        if let Some(sender) = self.sender.as_ref() {
+            sender.keep_alive();
+        }
+        if let Some(receiver) = self.receiver.as_ref() {
+            receiver.keep_alive();
+        }
+    }
+
+    /// Whether we are holding on to a sender or receiver that is no use any more
+    fn lost_channel(&self) -> bool {
+        self.sender.as_ref().is_some_and(|s| !s.is_alive())
+            || self.receiver.as_ref().is_some_and(|r| !r.is_alive())
+    }
+
+    /// Make a new channel and join it
+    pub fn new_channel(&mut self) -> Option<Uuid> {
+        match self.router.new_channel() {
+            Some(id) => {
+                self.set_channel(id);
+                Some(id)
+            }
+            None => {
+                self.host.log("no room for any more channels".into());
+                None
+            }
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.detach();
+
+        self.mode = mode;
+        self.dry_delay.clear();
+        self.loop_delay.clear();
+        self.limiter.reset();
+        self.update_mixer(self.mixer_layout);
+        self.attach();
+        self.report_latency();
+        self.announce();
+    }
+
+    pub fn set_latency(&mut self, latency: usize) {
+        self.latency = latency;
+        self.in_sync = false;
+        self.report_latency();
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.report_latency();
+        self.limiter.set_sample_rate(sample_rate);
+        self.drift.set_sample_rate(sample_rate);
+        self.resampler = Resampler::new(self.source_rate, sample_rate);
+        self.in_sync = false;
+        self.input_meter.set_sample_rate(sample_rate);
+        self.channel_meter.set_sample_rate(sample_rate);
+        self.loop_delay.clear();
+        self.apply_params();
+    }
+
+    pub fn set_tempo(&mut self, tempo: f32) {
+        self.tempo = tempo;
+        self.apply_params();
+        self.host.try_notify(PluginStateChange::Tempo(tempo));
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn tempo(&self) -> f32 {
+        self.tempo
+    }
+
+    pub fn set_alignment(&mut self, alignment: AlignmentMode) {
+        self.alignment = alignment;
+        self.in_sync = false;
+        self.send_alignment();
+    }
+
+    fn send_alignment(&self) {
+        self.host
+            .notify(PluginStateChange::Alignment(self.alignment));
+    }
+
+    /// Where the host's song is at the start of the next block, in samples, or
+    /// None if it isn't playing. Carries on by itself as we render, so only
+    /// needs setting when the host says it changed.
+    pub fn set_song_position(&mut self, position: Option<u64>) {
+        self.song_position = position;
+    }
+
+    /// Same as [`Engine::set_song_position`], for hosts that say where their song
+    /// is every block. Hosts only know that to within a few samples, so unless they
+    /// are further out than that we keep our own count, and blocks follow on exactly.
+    pub fn follow_song_position(&mut self, position: Option<u64>) {
+        let slack = self.sample_rate as u64 * SONG_POSITION_SLACK_MS / 1000;
+        match (self.song_position, position) {
+            (Some(ours), Some(theirs)) if ours.abs_diff(theirs) <= slack => {}
+            _ => self.song_position = position,
+        }
+    }
+
+    /// Latency we add to the signal, which is only ever anything when receiving
+    pub fn current_latency(&self) -> usize {
+        if self.mode.is_receiving() {
+            self.running_latency()
+        } else {
+            0
+        }
+    }
+
+    /// Latency we receive at, which is what was asked for unless the resampler
+    /// alone holds on to more than that
+    fn running_latency(&self) -> usize {
+        let nominal = self.source_rate as f64 / self.sample_rate.max(1) as f64;
+        let least = (self.resampler.delay() as f64 / nominal).ceil() as usize;
+        self.latency.max(least)
+    }
+
+    /// Tell the host (for delay compensation) and the UI what our latency is
+    pub fn report_latency(&mut self) {
+        let reported = self.current_latency();
+        self.host.set_latency(reported as u32);
+
+        self.host.notify(PluginStateChange::Latency {
+            setting: self.latency,
+            reported,
+            sample_rate: self.sample_rate,
+        });
+    }
+
+    /// Join our channel as either a sender or a receiver depending on our mode
+    fn attach(&mut self) {
+        let Some(uuid) = self.uuid.as_ref() else {
+            return;
+        };
+
+        self.in_sync = false;
+        self.underrun.reset();
+
+        if self.mode.is_receiving() {
+            self.receiver = self.router.subscribe(uuid);
+        } else {
+            self.sender = self.router.add_sender(uuid);
+        }
+
+        // NOTE(emily): Counted once here rather than for every block we can't send
+        if self.receiver.is_none() && self.sender.is_none() {
+            if let Some(channel) = self.router.channel(uuid) {
+                channel.record_turned_away();
+                self.host.log(format!("no room for us on channel {}", uuid));
+            }
+        }
+    }
+
+    pub fn detach(&mut self) {
+        self.receiver = None;
+        self.sender = None;
+    }
+
+    pub fn send_available_channels(&self) {
+        self.host
+            .notify(PluginStateChange::AvailableChannels(self.router.channels()));
+    }
+
+    fn send_channel_id(&self) {
+        self.host.notify(PluginStateChange::ChannelId(
+            self.uuid.unwrap_or(Uuid::nil()),
+        ));
+    }
+
+    fn send_mode(&self) {
+        self.host.notify(PluginStateChange::Mode(self.mode));
+    }
+
+    fn send_port(&self) {
+        self.host.notify(PluginStateChange::Port(self.port));
+    }
+
+    fn send_label(&self) {
+        self.host
+            .notify(PluginStateChange::Label(self.label.clone()));
+    }
+
+    fn send_instances(&self) {
+        self.host.notify(PluginStateChange::Instances {
+            ours: self.instance,
+            instances: self.router.instances(),
+        });
+    }
+
+    /// Send everything the UI shows, for when it is opened
+    pub fn send_ui_state(&mut self) {
+        self.send_available_channels();
+        self.report_latency();
+        self.send_underrun_behaviour();
+        self.send_alignment();
+        self.send_params();
+        self.send_channel_stats();
+        self.send_label();
+        self.send_port();
+        self.send_instances();
+    }
+
+    /// Fill `output` from our channel, returns false if there was not enough to go around
+    fn receive_samples(&mut self, output: &mut [Sample]) -> bool {
+        let Some(layout) = self.receiver.as_ref().map(ChannelReceiver::layout) else {
+            return false;
+        };
+        if layout != self.mixer_layout {
+            self.update_mixer(layout);
+        }
+        let latency = self.running_latency();
+
+        let Some(receiver) = self.receiver.as_mut() else {
+            return false;
+        };
+        // NOTE(emily): Same as in `send_from`, rendering is what keeps our tap ours
+        receiver.keep_alive();
+
+        // Wait for housekeeping to set us up for a channel sent at a different rate
+        if receiver
+            .sample_rate()
+            .is_some_and(|rate| rate != self.source_rate)
+        {
+            self.in_sync = false;
+            return false;
+        }
+
+        // Follow the song when we can, otherwise fall back to whatever is waiting for us
+        if self.alignment == AlignmentMode::Transport && self.source_rate == self.sample_rate {
+            if let Some(position) = self.song_position {
+                let wanted = position.checked_sub(latency as u64);
+                match wanted.map_or(Located::Nowhere, |p| receiver.locate(p)) {
+                    Located::At(start) => return self.receive_aligned(start, output),
+                    // NOTE(emily): Nothing was sent for this part of the song, which is
+                    // silence rather than an underrun
+                    Located::Nowhere => {
+                        output.fill([0.0; 2]);
+                        self.in_sync = false;
+                        return true;
+                    }
+                    Located::Untimed => {}
+                }
+            }
+        }
+
+        // Latency is in our samples, but what is waiting for us is in the senders'
+        let nominal = self.source_rate as f64 / self.sample_rate as f64;
+        let in_source = |len: usize| (len as f64 * nominal).round() as usize;
+
+        // NOTE(emily): We sit exactly `latency` samples behind the senders so that the delay
+        // we report to the host is the delay we actually have. Senders that render before us
+        // in a block period have already written this period's block, which we mustn't count,
+        // or we would hear it a block sooner than we say. Nothing but the time tells a block
+        // from this period apart from the one before, so anything rendered less than half a
+        // block ago is taken to be from this period. Part of that latency is spent in the resampler.
+        let target = in_source(latency).saturating_sub(self.resampler.delay());
+        let half_block = output.len() as u64 * 500_000 / self.sample_rate.max(1) as u64;
+        let ahead = receiver.rendered_since(self.host.now().saturating_sub(half_block));
+        if !self.in_sync {
+            // Start far enough back to fill the resampler's history as well
+            let history = &mut self.scratch[..self.resampler.history_len()];
+            receiver.seek(target + ahead + history.len());
+            read_mixed(receiver, layout, &self.mixer, &mut self.frames, history);
+            self.resampler.reset(history);
+            self.drift.reset();
+            self.in_sync = true;
+        }
+
+        let available = receiver.available();
+        if available > in_source(latency + output.len()) + ahead {
+            receiver.record_dropped();
+            self.in_sync = false;
+            return false;
+        }
+
+        // NOTE(emily): Senders in another process, or on another thread, run off a clock that
+        // is never quite the same as ours. Read ever so slightly faster or slower to keep
+        // however much is waiting for us centred on where it should be, rather than slowly
+        // running dry or overflowing.
+        let ratio = nominal
+            * self
+                .drift
+                .update(available as f64 - (target + ahead) as f64, output.len());
+
+        // Leave room for reading a little more than a chunk's worth
+        let chunk_len = ((MAX_BLOCK / 2) as f64 / nominal.max(1.0)) as usize;
+        for chunk in output.chunks_mut(chunk_len) {
+            let needed = self.resampler.input_needed(chunk.len(), ratio);
+            if receiver.available() < needed {
+                receiver.record_underrun();
+                self.in_sync = false;
+                return false;
+            }
+
+            let input = &mut self.scratch[..needed];
+            read_mixed(receiver, layout, &self.mixer, &mut self.frames, input);
+            self.resampler.process(input, chunk, ratio);
+        }
+
+        true
+    }
+
+    /// Fill `output` with exactly what was sent from `start` in our channel onwards
+    fn receive_aligned(&mut self, start: u64, output: &mut [Sample]) -> bool {
+        let Some(receiver) = self.receiver.as_mut() else {
+            return false;
+        };
+        let layout = self.mixer_layout;
+
+        // NOTE(emily): We share a clock with the senders, so there is no drift to soak up
+        // and the resampler only ever reads at a ratio of 1. It still holds on to a little.
+        let wanted = start + self.resampler.delay() as u64;
+        if !self.in_sync || receiver.position() != wanted {
+            let history = &mut self.scratch[..self.resampler.history_len()];
+            receiver.seek_to(wanted - history.len() as u64);
+            read_mixed(receiver, layout, &self.mixer, &mut self.frames, history);
+            self.resampler.reset(history);
+            self.drift.reset();
+            self.in_sync = true;
+        }
+
+        for chunk in output.chunks_mut(MAX_BLOCK / 2) {
+            if receiver.available() < chunk.len() {
+                receiver.record_underrun();
+                self.in_sync = false;
+                return false;
+            }
+
+            let input = &mut self.scratch[..chunk.len()];
+            read_mixed(receiver, layout, &self.mixer, &mut self.frames, input);
+            self.resampler.process(input, chunk, 1.0);
+        }
+
+        true
+    }
+
+    /// Everything about us worth saving
+    pub fn state(&self) -> InstanceState {
+        InstanceState {
+            instance: Some(self.instance),
+            mode: self.mode,
+            channel: self
+                .uuid
+                .and_then(|uuid| self.router.channel_snapshot(&uuid)),
+            // NOTE(emily): FL only ever has one project open, so the instances in our
+            // process are the ones in our project. Other projects' channels stay theirs.
+            graph: self.router.graph(std::process::id()),
+            params: self.params,
+            latency: self.latency,
+            underrun_behaviour: self.underrun.behaviour,
+            label: self.label.clone(),
+            port: self.port,
+            alignment: self.alignment,
+        }
+    }
+
+    pub fn restore(&mut self, state: InstanceState) {
+        if let Some(instance) = state.instance {
+            self.adopt_instance(instance);
+        }
+
+        self.params = state.params;
+        self.apply_params();
+        self.send_params();
+
+        self.set_latency(state.latency);
+        self.set_underrun_behaviour(state.underrun_behaviour);
+        self.set_alignment(state.alignment);
+        self.label = state.label;
+        self.send_label();
+        self.port = state.port;
+        self.send_port();
+
+        // NOTE(emily): Bring back every channel the project used, not just our own, so
+        // whichever instance loads first puts the whole graph back. Snapshots settle
+        // between themselves which is newest, so load order doesn't matter.
+        let graph = state.graph.iter().map(|g| &g.channel);
+        for snapshot in graph.chain(state.channel.as_ref()) {
+            if !self.router.restore_channel(snapshot) {
+                self.host
+                    .log(format!("no room for channel {}", snapshot.info.id));
+            }
+        }
+
+        // Only join our channel once we know what we are joining it as
+        self.detach();
+        self.uuid = state.channel.map(|c| c.info.id);
+        self.set_mode(state.mode);
+
+        self.send_mode();
+        self.send_available_channels();
+        self.send_channel_id();
+
+        // NOTE(emily): Everyone else brings back their own mode and channel when they load,
+        // this only puts back anyone who loaded already but somehow isn't where they were.
+        let instances = self.router.instances();
+        for entry in &state.graph {
+            let channel = entry.channel.info.id;
+            for (id, _) in &entry.members {
+                let moved = instances
+                    .iter()
+                    .any(|i| i.id == *id && i.id != self.instance && i.channel != Some(channel));
+                if moved {
+                    self.router.request_channel(id, Some(channel));
+                }
+            }
+        }
+    }
+
+    /// Go back to being who we were when we were saved, so that the project's
+    /// graph still knows us
+    fn adopt_instance(&mut self, instance: Uuid) {
+        if instance == self.instance {
+            return;
+        }
+
+        // NOTE(emily): Whoever we were saved as may still be around, e.g. if we are a
+        // copy of another instance, in which case we stay somebody new.
+        if self.router.instances().iter().any(|i| i.id == instance) {
+            return;
+        }
+
+        self.router.withdraw(&self.instance);
+        self.instance = instance;
+        self.announce();
+    }
+
+    /// Save a preset, returning where it ended up
+    pub fn export_preset(&self, path: &Path) -> eyre::Result<PathBuf> {
+        let file = Preset::file(path)?;
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&file, Preset::new(&self.state()).to_json()?)?;
+        Ok(file)
+    }
+
+    /// Load a preset, returning where it came from
+    pub fn import_preset(&mut self, path: &Path) -> eyre::Result<PathBuf> {
+        let file = Preset::file(path)?;
+        let preset = Preset::from_json(&std::fs::read_to_string(&file)?)?;
+
+        // Join whichever channel has this name, making it if there isn't one yet.
+        // Presets without a channel leave us where we are.
+        let channel = match preset.channel_name {
+            Some(name) => {
+                let existing = self
+                    .router
+                    .channels()
+                    .into_iter()
+                    .find(|c| c.name == name)
+                    .map(|c| c.id);
+
+                let id = match existing {
+                    Some(id) => id,
+                    None => {
+                        let id = self
+                            .router
+                            .new_channel()
+                            .ok_or_else(|| eyre::eyre!("no room for any more channels"))?;
+                        self.router.rename_channel(&id, &name);
+                        if !self.router.set_channel_layout(&id, preset.channel_layout) {
+                            self.host.log(format!(
+                                "no room for {} to carry {}",
+                                name, preset.channel_layout
+                            ));
+                        }
+                        id
+                    }
+                };
+
+                self.router.channel_snapshot(&id)
+            }
+            None => self
+                .uuid
+                .and_then(|uuid| self.router.channel_snapshot(&uuid)),
+        };
+
+        self.restore(InstanceState {
+            instance: None,
+            mode: preset.mode,
+            channel,
+            graph: vec![],
+            params: preset.params,
+            latency: preset.latency,
+            underrun_behaviour: preset.underrun_behaviour,
+            label: self.label.clone(),
+            port: preset.port,
+            alignment: preset.alignment,
+        });
+
+        Ok(file)
+    }
+
+    fn send_preset_status(&self, status: String) {
+        self.host.log(status.clone());
+        self.host.notify(PluginStateChange::PresetStatus(status));
+    }
+
+    pub fn set_underrun_behaviour(&mut self, behaviour: UnderrunBehaviour) {
+        self.underrun.behaviour = behaviour;
+        self.send_underrun_behaviour();
+    }
+
+    fn send_underrun_behaviour(&self) {
+        self.host.notify(PluginStateChange::UnderrunBehaviour(
+            self.underrun.behaviour,
+        ));
+    }
+
+    /// Fill `output` from our channel, covering up any gaps
+    fn receive_into(&mut self, output: &mut [Sample]) {
+        if self.receiver.is_none() {
+            output.fill([0.0; 2]);
+        } else if self.receive_samples(output) {
+            self.underrun.received(output);
+        } else {
+            self.underrun.underrun(output);
+        }
+    }
+
+    fn send_from(&mut self, input: &[Sample]) {
+        // NOTE(emily): Alive means rendering. Housekeeping only runs when the host sends
+        // us messages or idles us, which it may not do for a long time while it renders.
+        if let Some(sender) = self.sender.as_ref() {
+            sender.keep_alive();
+        }
+
+        // NOTE(emily): The channel keeps a fixed amount of history, receivers that
+        // fall behind are skipped forward, so the sender never has to wait on anyone.
+        let rendered_at = self.host.now();
+        for (i, chunk) in input.chunks(self.scratch.len()).enumerate() {
+            let offset = (i * self.scratch.len()) as u64;
+            let timing = Timing {
+                sample_counter: self.sample_counter + offset,
+                song_position: self.song_position.map(|p| p + offset),
+                rendered_at,
+            };
+
+            let scratch = &mut self.scratch[..chunk.len()];
+            for (o, i) in scratch.iter_mut().zip(chunk) {
+                let gain = self.send_gain.tick();
+                *o = [i[0] * gain, i[1] * gain];
+            }
+
+            self.channel_meter.process(scratch, &self.meters.channel);
+            self.meters.scope.write(scratch);
+
+            let Some(layout) = self.sender.as_ref().map(ChannelSender::layout) else {
+                continue;
+            };
+            if layout != self.mixer_layout {
+                self.update_mixer(layout);
+            }
+
+            let frames = &mut self.frames[..chunk.len()];
+            for (f, s) in frames.iter_mut().zip(self.scratch.iter()) {
+                *f = self.mixer.mix(&layout::to_frame(*s));
+            }
+
+            if let Some(sender) = self.sender.as_ref() {
+                sender.send(frames, layout, self.sample_rate, timing);
+            }
+        }
+    }
+
+    /// Receive into `output` and run it through everything on the receive side
+    fn receive_wet(&mut self, output: &mut [Sample]) {
+        self.receive_into(output);
+
+        for s in output.iter_mut() {
+            let delay = self.loop_delay_time.tick();
+            let gain = self.receive_gain.tick();
+            let delayed = self.loop_delay.tick(*s, delay);
+            *s = [delayed[0] * gain, delayed[1] * gain];
+        }
+
+        self.limiter.process(output);
+
+        self.channel_meter.process(output, &self.meters.channel);
+        self.meters.scope.write(output);
+    }
+
+    /// Set a parameter to a normalised value. If the change didn't come from the host
+    /// then let it know, so that it can record automation.
+    pub fn set_param(&mut self, param: Param, value: f32, from_host: bool) {
+        self.params.set(param, value);
+        self.apply_params();
+
+        if !from_host {
+            self.host.parameter_changed(param, self.params.get(param));
+        }
+
+        // NOTE(emily): Automation can arrive on the audio thread, so don't wait on the UI here.
+        self.host
+            .try_notify(PluginStateChange::Param(param, self.params.get(param)));
+    }
+
+    /// Push our parameters through to everything that depends on them
+    fn apply_params(&mut self) {
+        self.limiter.set_ceiling(self.params.ceiling());
+        self.loop_delay_time.set_target(
+            self.params.delay_samples(self.sample_rate, self.tempo),
+            self.sample_rate,
+        );
+
+        let sample_rate = self.sample_rate;
+        self.send_gain
+            .set_target(self.params.routed_gain(Param::SendGain), sample_rate);
+        self.receive_gain
+            .set_target(self.params.routed_gain(Param::ReceiveGain), sample_rate);
+        self.mix
+            .set_target(self.params.get(Param::Mix), sample_rate);
+    }
+
+    fn send_params(&self) {
+        for param in Param::ALL {
+            self.host
+                .notify(PluginStateChange::Param(param, self.params.get(param)));
+        }
+    }
+
+    /// Log any underruns since we last checked and let the UI know.
+    /// Not to be called from the audio thread.
+    fn report_underruns(&mut self) {
+        let underruns = self.underrun.underruns;
+        if underruns != self.logged_underruns {
+            self.host.log(format!(
+                "underrun: {} times",
+                underruns - self.logged_underruns
+            ));
+            self.logged_underruns = underruns;
+
+            self.host.notify(PluginStateChange::Underruns(underruns));
+        }
+    }
+
+    /// Log anything the safety limiter had to throw away, and let the UI know
+    /// whether it has stepped in recently. Not to be called from the audio thread.
+    fn report_protection(&mut self) {
+        let scrubbed = self.limiter.scrubbed;
+        if scrubbed != self.logged_scrubbed {
+            self.host.log(format!(
+                "limiter: dropped {} non-finite samples",
+                scrubbed - self.logged_scrubbed
+            ));
+            self.logged_scrubbed = scrubbed;
+        }
+
+        if std::mem::take(&mut self.limiter.engaged) {
+            self.last_protection = Some(Instant::now());
+        }
+
+        let engaged = self
+            .last_protection
+            .is_some_and(|t| t.elapsed() < PROTECTION_HOLD);
+
+        if engaged != self.reported_protection {
+            self.reported_protection = engaged;
+            self.host.notify(PluginStateChange::Protection(engaged));
+        }
+    }
+
+    fn send_channel_stats(&self) {
+        self.host.notify(PluginStateChange::ChannelStats(
+            self.uuid.and_then(|uuid| self.router.channel_stats(&uuid)),
+        ));
+    }
+
+    /// Set up to read a channel that is sent at a different rate to the one it was
+    fn update_source_rate(&mut self) {
+        let Some(rate) = self.receiver.as_ref().and_then(|r| r.sample_rate()) else {
+            return;
+        };
+
+        if rate != self.source_rate {
+            self.host.log(format!(
+                "channel is now sent at {} Hz, we run at {} Hz",
+                rate, self.sample_rate
+            ));
+            self.source_rate = rate;
+            self.resampler = Resampler::new(rate, self.sample_rate);
+            self.in_sync = false;
+            self.report_latency();
+        }
+    }
+
+    /// Keep on top of anything that doesn't need to happen on the audio thread
+    pub fn housekeeping(&mut self) {
+        self.report_underruns();
+        self.report_protection();
+
+        // NOTE(emily): Setting up a resampler allocates, so it doesn't happen on the audio thread
+        self.update_source_rate();
+
+        // NOTE(emily): These go out all the time, so if the UI is behind it can
+        // catch the next ones rather than hold up the host.
+        if self.last_stats.elapsed() > STATS_INTERVAL {
+            self.last_stats = Instant::now();
+            self.host.try_notify(PluginStateChange::ChannelStats(
+                self.uuid.and_then(|uuid| self.router.channel_stats(&uuid)),
+            ));
+            self.host.try_notify(PluginStateChange::Instances {
+                ours: self.instance,
+                instances: self.router.instances(),
+            });
+            self.host
+                .try_notify(PluginStateChange::Drift(self.drift_ppm()));
+        }
+
+        if self.last_announce.elapsed() > ANNOUNCE_INTERVAL {
+            self.announce();
+            self.keep_alive();
+        }
+
+        // Somebody else's matrix asked us to move
+        match self.router.take_channel_request(&self.instance) {
+            Some(Some(uuid)) => self.set_channel(uuid),
+            Some(None) => self.leave_channel(),
+            None => {}
+        }
+
+        if self.last_gc.elapsed() > GC_INTERVAL {
+            self.last_gc = Instant::now();
+
+            let removed = self.router.collect_garbage(UNUSED_CHANNEL_TIMEOUT);
+            if !removed.is_empty() {
+                self.host
+                    .log(format!("removed {} unused channels", removed.len()));
+                self.send_available_channels();
+            }
+        }
+
+        // If our channel was deleted out from under us, let go of it. If it was made
+        // again under the same id, or we lost our place on it, join it again.
+        if let Some(uuid) = self.uuid {
+            if self.router.channel(&uuid).is_none() {
+                self.host.log(format!("channel {} went away", uuid));
+                self.leave_channel();
+                self.send_available_channels();
+            } else if self.lost_channel() {
+                self.host.log(format!(
+                    "lost our place on channel {}, joining it again",
+                    uuid
+                ));
+                self.detach();
+                self.attach();
+            }
+        }
+    }
+
+    /// Act on a message from the UI. Anything to do with the editor window itself
+    /// is left to whoever owns it.
+    pub fn handle_message(&mut self, message: PluginMessage) {
+        match message {
+            PluginMessage::SetEditor(_) => {}
+            PluginMessage::NewChannel => {
+                self.new_channel();
+            }
+            PluginMessage::SelectChannel(id) => self.set_channel(id),
+            PluginMessage::RenameChannel(id, name) => {
+                self.router.rename_channel(&id, &name);
+                self.send_available_channels();
+            }
+            PluginMessage::SetChannelColour(id, colour) => {
+                self.router.set_channel_colour(&id, colour);
+                self.send_available_channels();
+            }
+            PluginMessage::SetChannelLayout(id, layout) => {
+                if !self.router.set_channel_layout(&id, layout) {
+                    self.host.log(format!(
+                        "no room for another channel wider than stereo, {} stays as it is",
+                        id
+                    ));
+                }
+                self.send_available_channels();
+            }
+            PluginMessage::SetPort(port) => self.set_port(port),
+            PluginMessage::DeleteChannel(id) => {
+                self.router.delete_channel(&id);
+                self.housekeeping();
+                self.send_available_channels();
+            }
+            PluginMessage::SetMode(mode) => self.set_mode(mode),
+            PluginMessage::SetLatency(latency) => self.set_latency(latency),
+            PluginMessage::SetUnderrunBehaviour(behaviour) => {
+                self.set_underrun_behaviour(behaviour)
+            }
+            PluginMessage::SetAlignment(alignment) => self.set_alignment(alignment),
+            PluginMessage::SetParam(param, value) => self.set_param(param, value, false),
+            PluginMessage::SetLabel(label) => self.set_label(label),
+            PluginMessage::Connect(instance, channel) => {
+                if instance == self.instance {
+                    match channel {
+                        Some(uuid) => self.set_channel(uuid),
+                        None => self.leave_channel(),
+                    }
+                } else if !self.router.request_channel(&instance, channel) {
+                    self.host.log(format!("instance {} went away", instance));
+                }
+                self.send_instances();
+            }
+            PluginMessage::ExportPreset(path) => {
+                let status = match self.export_preset(&path) {
+                    Ok(file) => format!("saved preset to {}", file.display()),
+                    Err(err) => format!("unable to save preset: {}", err),
+                };
+                self.send_preset_status(status);
+            }
+            PluginMessage::ImportPreset(path) => {
+                let status = match self.import_preset(&path) {
+                    Ok(file) => format!("loaded preset from {}", file.display()),
+                    Err(err) => format!("unable to load preset: {}", err),
+                };
+                self.send_preset_status(status);
+            }
+            PluginMessage::AskChannels => self.send_available_channels(),
+        }
+    }
+
+    pub fn render(&mut self, input: &[Sample], output: &mut [Sample]) {
+        self.input_meter.process(input, &self.meters.input);
+
+        match self.mode {
+            Mode::Receiver => {
+                self.receive_wet(output);
+            }
+            Mode::Sender => {
+                self.send_from(input);
+                output.fill([0.0; 2]);
+            }
+            Mode::Tap => {
+                self.send_from(input);
+                output.copy_from_slice(input);
+            }
+            Mode::Return => {
+                self.receive_wet(output);
+
+                // NOTE(emily): Delay our input by as much as we delay what we receive so that
+                // the whole output sits at the latency we report.
+                let latency = self.running_latency();
+                for (o, i) in output.iter_mut().zip(input) {
+                    let dry = self.dry_delay.tick(*i, latency);
+                    let mix = self.mix.tick();
+                    o[0] = dry[0] * (1.0 - mix) + o[0] * mix;
+                    o[1] = dry[1] * (1.0 - mix) + o[1] * mix;
+                }
+            }
+        }
+
+        let len = input.len() as u64;
+        self.sample_counter += len;
+        self.song_position = self.song_position.map(|p| p + len);
+    }
+}
+
+impl<H: Host> Drop for Engine<H> {
+    fn drop(&mut self) {
+        self.router.withdraw(&self.instance);
+    }
+}
+
+/// Read `output.len()` frames from a channel with this layout, mixed down to our stereo
+fn read_mixed(
+    receiver: &mut ChannelReceiver,
+    layout: Layout,
+    mixer: &Mixer,
+    frames: &mut [Frame],
+    output: &mut [Sample],
+) {
+    let frames = &mut frames[..output.len()];
+    receiver.read(frames, layout);
+    for (o, f) in output.iter_mut().zip(frames.iter()) {
+        *o = layout::to_sample(&mixer.mix(f));
+    }
+}
@@ -0,0 +1,385 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Sample;
+
+/// Most lanes a channel can carry
+pub const MAX_WIDTH: usize = 8;
+
+/// One sample for every lane of a channel
+pub type Frame = [f32; MAX_WIDTH];
+
+/// -3dB, for folding one speaker into two
+const FOLD: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Speakers everything is mixed through on its way from one layout to another
+const SPEAKERS: usize = 6;
+const L: usize = 0;
+const R: usize = 1;
+const C: usize = 2;
+// LFE is 3, which is only ever carried through by 5.1
+const LS: usize = 4;
+const RS: usize = 5;
+
+/// What the lanes of a channel are for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Layout {
+    Mono,
+    #[default]
+    Stereo,
+    /// Mid in the first lane, side in the second
+    MidSide,
+    /// Front left and right, then surround left and right
+    Quad,
+    /// Left, right, centre, LFE, surround left and right
+    Surround51,
+    /// Lanes that mean nothing in particular, that are only ever passed through
+    Discrete(u8),
+}
+
+impl Layout {
+    /// Layouts on offer in the editor
+    pub const ALL: [Layout; 7] = [
+        Layout::Mono,
+        Layout::Stereo,
+        Layout::MidSide,
+        Layout::Quad,
+        Layout::Surround51,
+        Layout::Discrete(4),
+        Layout::Discrete(8),
+    ];
+
+    /// Number of lanes
+    pub fn width(&self) -> usize {
+        match self {
+            Layout::Mono => 1,
+            Layout::Stereo | Layout::MidSide => 2,
+            Layout::Quad => 4,
+            Layout::Surround51 => 6,
+            Layout::Discrete(n) => (*n as usize).clamp(1, MAX_WIDTH),
+        }
+    }
+
+    /// How this is kept in the shared registry, where zeroed memory means stereo
+    pub fn to_bits(self) -> u32 {
+        match self {
+            Layout::Stereo => 0,
+            Layout::Mono => 1,
+            Layout::MidSide => 2,
+            Layout::Quad => 3,
+            Layout::Surround51 => 4,
+            Layout::Discrete(n) => 0x100 | n as u32,
+        }
+    }
+
+    pub fn from_bits(bits: u32) -> Self {
+        match bits {
+            1 => Layout::Mono,
+            2 => Layout::MidSide,
+            3 => Layout::Quad,
+            4 => Layout::Surround51,
+            bits if bits & 0x100 != 0 => Layout::Discrete(bits as u8),
+            _ => Layout::Stereo,
+        }
+    }
+
+    /// How each of our lanes feeds each speaker
+    fn upmix(self) -> Option<[[f32; MAX_WIDTH]; SPEAKERS]> {
+        let mut m = [[0.0; MAX_WIDTH]; SPEAKERS];
+        match self {
+            // NOTE(emily): Mono goes to both sides rather than the centre, so that it comes
+            // back out of stereo at the same level it went in.
+            Layout::Mono => {
+                m[L][0] = 1.0;
+                m[R][0] = 1.0;
+            }
+            Layout::Stereo => {
+                m[L][0] = 1.0;
+                m[R][1] = 1.0;
+            }
+            Layout::MidSide => {
+                m[L] = [1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+                m[R] = [1.0, -1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+            }
+            Layout::Quad => {
+                m[L][0] = 1.0;
+                m[R][1] = 1.0;
+                m[LS][2] = 1.0;
+                m[RS][3] = 1.0;
+            }
+            Layout::Surround51 => {
+                for (speaker, row) in m.iter_mut().enumerate() {
+                    row[speaker] = 1.0;
+                }
+            }
+            Layout::Discrete(_) => return None,
+        }
+        Some(m)
+    }
+
+    /// How each speaker feeds each of our lanes
+    fn downmix(self) -> Option<[[f32; SPEAKERS]; MAX_WIDTH]> {
+        // Folding everything down to stereo is where mono and mid/side start from too.
+        // LFE is left out of anything that has nowhere to put it.
+        let mut stereo = [[0.0; SPEAKERS]; 2];
+        stereo[0][L] = 1.0;
+        stereo[0][C] = FOLD;
+        stereo[0][LS] = FOLD;
+        stereo[1][R] = 1.0;
+        stereo[1][C] = FOLD;
+        stereo[1][RS] = FOLD;
+
+        let mut m = [[0.0; SPEAKERS]; MAX_WIDTH];
+        match self {
+            Layout::Mono => {
+                for s in 0..SPEAKERS {
+                    m[0][s] = 0.5 * (stereo[0][s] + stereo[1][s]);
+                }
+            }
+            Layout::Stereo => {
+                m[0] = stereo[0];
+                m[1] = stereo[1];
+            }
+            Layout::MidSide => {
+                for s in 0..SPEAKERS {
+                    m[0][s] = 0.5 * (stereo[0][s] + stereo[1][s]);
+                    m[1][s] = 0.5 * (stereo[0][s] - stereo[1][s]);
+                }
+            }
+            Layout::Quad => {
+                m[0] = [1.0, 0.0, FOLD, 0.0, 0.0, 0.0];
+                m[1] = [0.0, 1.0, FOLD, 0.0, 0.0, 0.0];
+                m[2][LS] = 1.0;
+                m[3][RS] = 1.0;
+            }
+            Layout::Surround51 => {
+                for (speaker, row) in m.iter_mut().take(SPEAKERS).enumerate() {
+                    row[speaker] = 1.0;
+                }
+            }
+            Layout::Discrete(_) => return None,
+        }
+        Some(m)
+    }
+}
+
+impl Display for Layout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Layout::Mono => write!(f, "Mono"),
+            Layout::Stereo => write!(f, "Stereo"),
+            Layout::MidSide => write!(f, "Mid/side"),
+            Layout::Quad => write!(f, "Quad"),
+            Layout::Surround51 => write!(f, "5.1"),
+            Layout::Discrete(n) => write!(f, "{} discrete", n),
+        }
+    }
+}
+
+/// Where an instance's stereo input and output sit on its channel. Anything
+/// wider than stereo can be carried by one instance per pair of lanes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Port {
+    /// Mix our stereo up to whatever the channel carries, and everything it
+    /// carries back down to stereo
+    #[default]
+    Mix,
+    /// Carry one pair of the channel's lanes exactly as they are, e.g. the
+    /// surrounds of a 5.1 channel
+    Pair(u8),
+}
+
+impl Port {
+    /// Ports that make sense on a channel with this layout
+    pub fn all(layout: Layout) -> Vec<Port> {
+        std::iter::once(Port::Mix)
+            .chain((0..layout.width().div_ceil(2)).map(|p| Port::Pair(p as u8)))
+            .collect()
+    }
+}
+
+impl Display for Port {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Port::Mix => write!(f, "Mix"),
+            Port::Pair(p) => write!(f, "Lanes {}+{}", *p as usize * 2 + 1, *p as usize * 2 + 2),
+        }
+    }
+}
+
+/// Turns frames of one layout into frames of another
+pub struct Mixer {
+    /// How much of each input lane goes into each output lane
+    matrix: [[f32; MAX_WIDTH]; MAX_WIDTH],
+    inputs: usize,
+    outputs: usize,
+    /// Straight through, which we keep track of so that it stays bit exact
+    identity: bool,
+}
+
+impl Mixer {
+    pub fn new(from: Layout, to: Layout) -> Self {
+        let (inputs, outputs) = (from.width(), to.width());
+        let mut matrix = [[0.0; MAX_WIDTH]; MAX_WIDTH];
+
+        match (from.upmix(), to.downmix()) {
+            (Some(up), Some(down)) if from != to => {
+                for (o, row) in matrix.iter_mut().enumerate().take(outputs) {
+                    for (i, m) in row.iter_mut().enumerate().take(inputs) {
+                        *m = (0..SPEAKERS).map(|s| down[o][s] * up[s][i]).sum();
+                    }
+                }
+            }
+            // Same layout, or lanes without any meaning, go across as they are
+            _ => {
+                for (lane, row) in matrix.iter_mut().enumerate().take(inputs.min(outputs)) {
+                    row[lane] = 1.0;
+                }
+            }
+        }
+
+        Self::from_matrix(matrix, inputs, outputs)
+    }
+
+    fn from_matrix(matrix: [[f32; MAX_WIDTH]; MAX_WIDTH], inputs: usize, outputs: usize) -> Self {
+        let identity = inputs == outputs
+            && matrix.iter().enumerate().all(|(o, row)| {
+                row.iter()
+                    .enumerate()
+                    .all(|(i, &m)| m == if i == o && o < outputs { 1.0 } else { 0.0 })
+            });
+
+        Self {
+            matrix,
+            inputs,
+            outputs,
+            identity,
+        }
+    }
+
+    /// From our stereo to a channel with this layout
+    pub fn sending(port: Port, layout: Layout) -> Self {
+        match port {
+            Port::Mix => Self::new(Layout::Stereo, layout),
+            Port::Pair(p) => {
+                let width = layout.width();
+                let first = p as usize * 2;
+                let mut matrix = [[0.0; MAX_WIDTH]; MAX_WIDTH];
+                if first + 1 < width {
+                    matrix[first][0] = 1.0;
+                    matrix[first + 1][1] = 1.0;
+                } else if first < width {
+                    // Only one lane left, which gets both of our sides
+                    matrix[first] = [0.5, 0.5, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+                }
+                Self::from_matrix(matrix, 2, width)
+            }
+        }
+    }
+
+    /// From a channel with this layout to our stereo
+    pub fn receiving(port: Port, layout: Layout) -> Self {
+        match port {
+            Port::Mix => Self::new(layout, Layout::Stereo),
+            Port::Pair(p) => {
+                let width = layout.width();
+                let first = p as usize * 2;
+                let mut matrix = [[0.0; MAX_WIDTH]; MAX_WIDTH];
+                if first + 1 < width {
+                    matrix[0][first] = 1.0;
+                    matrix[1][first + 1] = 1.0;
+                } else if first < width {
+                    // Only one lane left, which goes to both of our sides
+                    matrix[0][first] = 1.0;
+                    matrix[1][first] = 1.0;
+                }
+                Self::from_matrix(matrix, width, 2)
+            }
+        }
+    }
+
+    pub fn mix(&self, input: &Frame) -> Frame {
+        if self.identity {
+            return *input;
+        }
+
+        let mut output = [0.0; MAX_WIDTH];
+        for (o, row) in output.iter_mut().zip(&self.matrix).take(self.outputs) {
+            for (i, m) in input.iter().zip(row).take(self.inputs) {
+                *o += i * m;
+            }
+        }
+        output
+    }
+}
+
+pub fn to_frame(s: Sample) -> Frame {
+    let mut frame = [0.0; MAX_WIDTH];
+    frame[..2].copy_from_slice(&s);
+    frame
+}
+
+pub fn to_sample(frame: &Frame) -> Sample {
+    [frame[0], frame[1]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(lanes: &[f32]) -> Frame {
+        let mut frame = [0.0; MAX_WIDTH];
+        frame[..lanes.len()].copy_from_slice(lanes);
+        frame
+    }
+
+    #[test]
+    fn same_layout_is_untouched() {
+        for layout in Layout::ALL {
+            let input = frame(&[0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8]);
+            let output = Mixer::new(layout, layout).mix(&input);
+            assert_eq!(output[..layout.width()], input[..layout.width()]);
+        }
+    }
+
+    #[test]
+    fn stereo_survives_mid_side() {
+        let stereo = frame(&[0.75, 0.25]);
+        let ms = Mixer::new(Layout::Stereo, Layout::MidSide).mix(&stereo);
+        assert_eq!(ms, frame(&[0.5, 0.25]));
+        assert_eq!(Mixer::new(Layout::MidSide, Layout::Stereo).mix(&ms), stereo);
+    }
+
+    #[test]
+    fn mono_comes_back_at_the_same_level() {
+        let mono = frame(&[0.5]);
+        let stereo = Mixer::new(Layout::Mono, Layout::Stereo).mix(&mono);
+        assert_eq!(stereo, frame(&[0.5, 0.5]));
+        assert_eq!(Mixer::new(Layout::Stereo, Layout::Mono).mix(&stereo), mono);
+    }
+
+    #[test]
+    fn surround_folds_down_to_stereo() {
+        let surround = frame(&[1.0, 0.0, 1.0, 1.0, 1.0, 0.0]);
+        let stereo = Mixer::new(Layout::Surround51, Layout::Stereo).mix(&surround);
+        assert_eq!(stereo, frame(&[1.0 + 2.0 * FOLD, FOLD]));
+    }
+
+    #[test]
+    fn pairs_pick_out_lanes() {
+        let surround = frame(&[0.1, 0.2, 0.3, 0.4, 0.5, 0.6]);
+        let back = Mixer::receiving(Port::Pair(2), Layout::Surround51).mix(&surround);
+        assert_eq!(back, frame(&[0.5, 0.6]));
+
+        let sent = Mixer::sending(Port::Pair(1), Layout::Surround51).mix(&frame(&[0.3, 0.4]));
+        assert_eq!(sent, frame(&[0.0, 0.0, 0.3, 0.4]));
+    }
+
+    #[test]
+    fn layouts_survive_the_registry() {
+        for layout in Layout::ALL {
+            assert_eq!(Layout::from_bits(layout.to_bits()), layout);
+        }
+    }
+}
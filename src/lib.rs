@@ -1,145 +1,150 @@
+pub mod dsp;
+pub mod engine;
+pub mod layout;
+pub mod message;
+pub mod params;
 pub mod router;
+pub mod state;
 pub mod ui;
+pub mod window_handle;
 
 use derive_more::Display;
+use engine::{Engine, Host as _};
 use fpsdk::{
     create_plugin,
-    plugin::{message::DebugLogMsg, Plugin, PluginProxy},
+    host::GetName,
+    plugin::{
+        message::{DebugLogMsg, GetInName, GetMixingTime, SetLatency},
+        Plugin, PluginProxy,
+    },
+    ProcessParam, ProcessParamFlags, Time, TimeFormat, ValuePtr,
 };
+use message::{PluginMessage, PluginStateChange};
+use params::{Param, Params};
 use parking_lot::Mutex;
 use router::SharedRouter;
 use serde::{Deserialize, Serialize};
-use std::{
-    collections::VecDeque, fmt::Debug, io::Read, panic::RefUnwindSafe, sync::mpsc::TrySendError,
-};
-use uuid::Uuid;
+use state::SaveState;
+use std::{fmt::Debug, io::Read, panic::RefUnwindSafe};
+
+pub type Sample = [f32; 2];
 
-type Sample = [f32; 2];
+/// Receive latencies (in samples) on offer
+pub const LATENCIES: [usize; 5] = [256, 512, 1024, 2048, 4096];
+const DEFAULT_LATENCY: usize = 1024;
 
 #[derive(Debug, PartialEq, Display, Clone, Copy, Eq, Serialize, Deserialize)]
 pub enum Mode {
     Receiver,
     Sender,
+    /// Send our input, and pass it through untouched
+    Tap,
+    /// Receive, and mix it in with our input
+    Return,
 }
 
 impl Mode {
-    const ALL: [Mode; 2] = [Mode::Receiver, Mode::Sender];
+    const ALL: [Mode; 4] = [Mode::Receiver, Mode::Sender, Mode::Tap, Mode::Return];
+
+    pub fn is_receiving(&self) -> bool {
+        matches!(self, Mode::Receiver | Mode::Return)
+    }
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
-pub enum SaveState {
-    Ver1 { mode: Mode, uuid: uuid::Uuid },
+/// How a receiver lines up what it plays with what was sent
+#[derive(Debug, PartialEq, Display, Clone, Copy, Eq, Default, Serialize, Deserialize)]
+pub enum AlignmentMode {
+    /// Play whatever has been waiting for us for as long as our latency
+    #[default]
+    Queue,
+    /// Play exactly what was sent for our song position, less our latency, so that
+    /// playback and export come out the same. Falls back to [`AlignmentMode::Queue`]
+    /// while the song is stopped, or the channel is sent at another rate.
+    Transport,
 }
 
-#[derive(Debug, Clone)]
-pub enum PluginStateChange {
-    AvailableChannels(Vec<Uuid>),
-    ChannelId(Uuid),
-    Mode(Mode),
+impl AlignmentMode {
+    const ALL: [AlignmentMode; 2] = [AlignmentMode::Queue, AlignmentMode::Transport];
 }
 
-struct Feedback {
+/// The plugin host, and the editor, as far as the engine is concerned
+struct FlHost {
     host: Mutex<fpsdk::host::Host>,
     tag: fpsdk::plugin::Tag,
-    handle: Option<fpsdk::plugin::PluginProxy>,
-    mode: Mode,
-    store: Mutex<VecDeque<Sample>>,
-    uuid: Option<uuid::Uuid>,
-    router: SharedRouter,
-
-    ui_handle: ui::UIHandle,
+    ui: ui::UISender,
 }
 
-impl std::fmt::Debug for Feedback {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Feedback")
-            .field("host", &self.host)
-            .field("tag", &self.tag)
-            .field("handle", &self.handle)
-            .field("mode", &self.mode)
-            .field("memory", &"Shmem { ... }")
-            .finish()
+impl FlHost {
+    /// Where FL's song is at the start of the block being rendered, in samples, or
+    /// None if something else is talking to FL right now
+    fn try_song_position(&self, sample_rate: u32) -> Option<u64> {
+        // NOTE(emily): This is asked on the audio thread, which mustn't wait on the UI or
+        // message threads to be done with the host.
+        let Time(ms, _) = self.host.try_lock()?.on_message(
+            self.tag,
+            GetMixingTime(TimeFormat::AbsoluteMs, Time(0.0, 0.0)),
+        );
+        Some((ms.max(0.0) * sample_rate as f64 / 1000.0).round() as u64)
     }
 }
 
-unsafe impl Send for Feedback {}
-unsafe impl Sync for Feedback {}
-
-impl Feedback {
+impl engine::Host for FlHost {
     fn log(&self, msg: String) {
         self.host.lock().on_message(self.tag, DebugLogMsg(msg));
     }
 
-    fn set_channel(&mut self, uuid: Uuid) {
-        // Clear our store
-        // Dump our channel
-        // Set our id
-        self.store.lock().clear();
-        self.router
-            .rx(&uuid)
-            .map(|c| while let Ok(_) = c.try_recv() {});
-
-        self.uuid = Some(uuid);
-
-        // Inform UI of this
-        self.send_channel_id();
+    fn set_latency(&self, latency: u32) {
+        self.host.lock().on_message(self.tag, SetLatency(latency));
     }
 
-    fn send_available_channels(&self) {
-        self.ui_handle
-            .send_sync(ui::UIMessage::StateChange(
-                PluginStateChange::AvailableChannels(self.router.ids()),
-            ))
-            .unwrap();
+    fn parameter_changed(&self, param: Param, value: f32) {
+        self.host.lock().on_parameter(
+            self.tag,
+            param.index(),
+            ValuePtr::from_raw(Params::to_host(value) as isize),
+        );
     }
 
-    fn send_channel_id(&self) {
-        self.ui_handle
-            .send_sync(ui::UIMessage::StateChange(PluginStateChange::ChannelId(
-                self.uuid.clone().or(Some(Uuid::nil())).unwrap(),
-            )))
-            .unwrap();
+    fn notify(&self, change: PluginStateChange) {
+        // NOTE(emily): The editor only goes away as we are being dropped, nobody is
+        // listening for this by then.
+        let _ = self.ui.send_sync(ui::UIMessage::StateChange(change));
     }
 
-    fn send_mode(&self) {
-        self.ui_handle
-            .send_sync(ui::UIMessage::StateChange(PluginStateChange::Mode(
-                self.mode,
-            )))
-            .unwrap();
+    fn try_notify(&self, change: PluginStateChange) {
+        let _ = self.ui.try_send(ui::UIMessage::StateChange(change));
     }
 
-    fn receive_samples(&mut self) {
-        const HIGH_MARK: usize = 4096;
-        const LOW_MARK: usize = 256;
-
-        let mut store = self.store.lock();
+    fn location(&self) -> Option<String> {
+        // NOTE(emily): An effect's first input is the mixer track it sits on
+        let track = self.host.lock().on_message(self.tag, GetInName(1));
+        (!track.name.is_empty()).then_some(track.name)
+    }
+}
 
-        // If we already have enough samples, early out
-        if store.len() > LOW_MARK {
-            return;
-        }
+struct Feedback {
+    engine: Engine<FlHost>,
+    handle: Option<fpsdk::plugin::PluginProxy>,
+    ui_handle: ui::UIHandle,
+    /// Whether FL's transport is running
+    playing: bool,
+}
 
-        // Try and receive more samples
-        if let Some(rx) = self.uuid.as_ref().and_then(|uuid| self.router.rx(uuid)) {
-            while store.len() < HIGH_MARK {
-                match rx.try_recv() {
-                    Ok(samples) => {
-                        for s in samples {
-                            store.push_back(s)
-                        }
-                    }
-                    Err(_err) => {
-                        break;
-                    }
-                }
-            }
-        } else {
-            self.log(format!("no rx?"));
-        }
+impl std::fmt::Debug for Feedback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Feedback")
+            .field("host", &self.engine.host().host)
+            .field("tag", &self.engine.host().tag)
+            .field("handle", &self.handle)
+            .field("mode", &self.engine.mode())
+            .field("memory", &"Shmem { ... }")
+            .finish()
     }
 }
 
+unsafe impl Send for Feedback {}
+unsafe impl Sync for Feedback {}
+
 // TODO(emily): This is what we call a _lie_
 impl RefUnwindSafe for Feedback {}
 
@@ -148,36 +153,40 @@ impl Plugin for Feedback {
     where
         Self: Sized,
     {
-        let router =
-            SharedRouter::new_or_open(&format!("emilydotgg-feedback-{}", std::process::id()));
+        let router = SharedRouter::new_or_open(&router::segment_name());
+        let ui_handle = ui::UIHandle::new();
 
-        Self {
+        let host = FlHost {
             host: Mutex::new(host),
             tag,
+            ui: ui_handle.sender(),
+        };
+
+        let engine = Engine::new(host, router);
+        ui_handle
+            .send_sync(ui::UIMessage::Meters(engine.meters()))
+            .unwrap();
+
+        Self {
+            engine,
             handle: None,
-            mode: Mode::Receiver,
-            store: Default::default(),
-            uuid: None,
-            ui_handle: ui::UIHandle::new(),
-            router,
+            ui_handle,
+            playing: false,
         }
     }
 
     fn info(&self) -> fpsdk::plugin::Info {
-        fpsdk::plugin::InfoBuilder::new_effect("emilydotgg-feedback", "feedback", 0)
-            .want_new_tick()
-            .build()
+        fpsdk::plugin::InfoBuilder::new_effect(
+            "emilydotgg-feedback",
+            "feedback",
+            Param::ALL.len() as u32,
+        )
+        .want_new_tick()
+        .build()
     }
 
     fn save_state(&mut self, writer: fpsdk::plugin::StateWriter) {
-        if let Some(uuid) = self.uuid {
-            let state = SaveState::Ver1 {
-                mode: self.mode,
-                uuid: uuid,
-            };
-
-            bincode::serialize_into(writer, &state).unwrap();
-        }
+        bincode::serialize_into(writer, &SaveState::new(self.engine.state())).unwrap();
     }
 
     fn load_state(&mut self, mut reader: fpsdk::plugin::StateReader) {
@@ -185,33 +194,24 @@ impl Plugin for Feedback {
         reader
             .read_to_end(&mut buf)
             .and_then(|_| {
-                bincode::deserialize::<SaveState>(&buf).map_err(|e| {
-                    std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        format!("error deserializing value {}", e),
-                    )
-                })
-            })
-            .map(|value| match value {
-                SaveState::Ver1 { mode, uuid } => {
-                    self.mode = mode;
-                    {
-                        if let None = self.router.channel(&uuid) {
-                            self.router.new_channel_with_id(&uuid);
-                        }
-                    }
-                    self.set_channel(uuid);
-                    self.send_mode();
-                }
+                bincode::deserialize::<SaveState>(&buf)
+                    .map_err(|e| std::io::Error::other(format!("error deserializing value {}", e)))
             })
-            .unwrap_or_else(|_e| self.log(format!("error reading state")));
+            .map(|value| self.engine.restore(value.migrate()))
+            .unwrap_or_else(|_e| self.engine.host().log("error reading state".into()));
         // No load state
     }
 
     fn on_message(&mut self, message: fpsdk::host::Message<'_>) -> Box<dyn fpsdk::AsRawPtr> {
         match message {
+            fpsdk::host::Message::SetSampleRate(sample_rate) => {
+                self.engine.set_sample_rate(sample_rate);
+            }
+            fpsdk::host::Message::SetPlaying(playing) => {
+                self.playing = playing;
+            }
             fpsdk::host::Message::ShowEditor(hwnd) => {
-                self.send_available_channels();
+                self.engine.send_ui_state();
                 self.ui_handle
                     .send_sync(ui::UIMessage::ShowEditor(hwnd.into()))
                     .unwrap();
@@ -219,58 +219,79 @@ impl Plugin for Feedback {
             _ => {}
         }
 
+        self.engine.housekeeping();
+
         // TODO(emily): This really needs to happen somewhere else
         while let Ok(msg) = self.ui_handle.rx.try_recv() {
             match msg {
-                ui::PluginMessage::SetEditor(hwnd) => {
+                PluginMessage::SetEditor(hwnd) => {
                     if let Some(handle) = self.handle.as_ref() {
-                        handle.set_editor_hwnd(hwnd.as_ptr().unwrap_or(0 as *mut c_void));
+                        handle.set_editor_hwnd(hwnd.as_ptr().unwrap_or(std::ptr::null_mut()));
                     }
                 }
-                ui::PluginMessage::NewChannel => {
-                    let id = self.router.new_channel();
-                    self.set_channel(id);
-                }
-                ui::PluginMessage::SelectChannel(id) => self.set_channel(id),
-                ui::PluginMessage::SetMode(mode) => self.mode = mode,
-                ui::PluginMessage::AskChannels => self.send_available_channels(),
+                msg => self.engine.handle_message(msg),
             }
         }
         Box::new(0)
     }
 
-    fn name_of(&self, _value: fpsdk::host::GetName) -> String {
-        "No names".into()
+    fn idle(&mut self) {
+        self.engine.housekeeping();
+    }
+
+    fn process_param(&mut self, message: ProcessParam) -> Box<dyn fpsdk::AsRawPtr> {
+        let Some(param) = Param::from_index(message.index) else {
+            return Box::new(0);
+        };
+
+        if message.flags.contains(ProcessParamFlags::UPDATE_VALUE) {
+            let value = Params::from_host(message.value.get::<i32>());
+            self.engine.set_param(param, value, true);
+        } else if message.flags.contains(ProcessParamFlags::GET_VALUE) {
+            return Box::new(Params::to_host(self.engine.params().get(param)));
+        }
+
+        Box::new(0)
+    }
+
+    fn name_of(&self, value: GetName) -> String {
+        match value {
+            GetName::Param(index) => Param::from_index(index).map(|p| p.to_string()),
+            GetName::ParamValue(index, value) => Param::from_index(index).map(|p| {
+                p.format(
+                    Params::from_host(value as i32),
+                    self.engine.params(),
+                    self.engine.sample_rate(),
+                    self.engine.tempo(),
+                )
+            }),
+            _ => None,
+        }
+        .unwrap_or_else(|| "No names".into())
+    }
+
+    fn process_event(&mut self, event: fpsdk::host::Event) {
+        if let fpsdk::host::Event::Tempo(tempo, _) = event {
+            self.engine.set_tempo(tempo);
+        }
     }
 
     fn render(&mut self, input: &[[f32; 2]], output: &mut [[f32; 2]]) {
-        match self.mode {
-            Mode::Receiver => {
-                self.receive_samples();
-
-                let mut store = self.store.lock();
-                if store.len() < output.len() {
-                    self.log(format!("underrun: {} vs {}", store.len(), output.len()));
-                    return;
-                } else {
-                    for os in output.iter_mut() {
-                        *os = store.pop_front().unwrap();
-                    }
-                }
-            }
-            Mode::Sender => {
-                if let Some(tx) = self.uuid.as_ref().and_then(|uuid| self.router.tx(uuid)) {
-                    // NOTE(emily): We try send here, as we don't care whether this channel is full or not
-                    // if it is full, there is probably no receiver anyway, so we just dump data here.
-
-                    match tx.try_send(Vec::from(input)) {
-                        Err(TrySendError::Full(_)) | Ok(_) => Ok(()),
-                        e => e,
-                    }
-                    .unwrap()
-                }
-            }
+        // NOTE(emily): When FL is busy, our own count carries on from wherever it last
+        // said its song was, a block further along each block.
+        if !self.playing {
+            self.engine.follow_song_position(None);
+        } else if let Some(position) = self
+            .engine
+            .host()
+            .try_song_position(self.engine.sample_rate())
+        {
+            self.engine.follow_song_position(Some(position));
         }
+
+        // TODO(emily): Ask FL for our extra input and output ports as well, so that one
+        // instance can carry every lane of a wide channel rather than one pair of them.
+        self.engine.render(input, output);
     }
 
     fn proxy(&mut self, handle: PluginProxy) {
@@ -280,6 +301,7 @@ impl Plugin for Feedback {
 
 impl Drop for Feedback {
     fn drop(&mut self) {
+        self.engine.detach();
         self.ui_handle.send_sync(ui::UIMessage::Die).unwrap();
         self.ui_handle.join();
     }
@@ -0,0 +1,77 @@
+//! What the plugin and its editor tell each other
+
+use std::path::PathBuf;
+
+use uuid::Uuid;
+
+use crate::{
+    dsp::underrun::UnderrunBehaviour,
+    layout::{Layout, Port},
+    params::Param,
+    router::{ChannelInfo, ChannelStats, Colour, InstanceInfo},
+    window_handle::WindowHandle,
+    AlignmentMode, Mode,
+};
+
+/// Message sent from UI to Plugin
+#[derive(Debug)]
+pub enum PluginMessage {
+    SetEditor(WindowHandle),
+    SetMode(Mode),
+    NewChannel,
+    SelectChannel(Uuid),
+    RenameChannel(Uuid, String),
+    SetChannelColour(Uuid, Option<Colour>),
+    SetChannelLayout(Uuid, Layout),
+    /// Where our stereo sits on our channel
+    SetPort(Port),
+    DeleteChannel(Uuid),
+    SetLatency(usize),
+    SetUnderrunBehaviour(UnderrunBehaviour),
+    SetAlignment(AlignmentMode),
+    SetParam(Param, f32),
+    SetLabel(String),
+    /// Put an instance, which may well be us, on a channel or take it off its channel
+    Connect(Uuid, Option<Uuid>),
+    ExportPreset(PathBuf),
+    ImportPreset(PathBuf),
+    AskChannels,
+}
+
+/// Message sent from Plugin to UI about what has changed
+#[derive(Debug, Clone)]
+pub enum PluginStateChange {
+    AvailableChannels(Vec<ChannelInfo>),
+    ChannelId(Uuid),
+    Mode(Mode),
+    /// What other instances see us as
+    Label(String),
+    /// Where our stereo sits on our channel
+    Port(Port),
+    /// Every instance we know of, and which of them is us
+    Instances {
+        ours: Uuid,
+        instances: Vec<InstanceInfo>,
+    },
+    Latency {
+        /// Latency we use when receiving
+        setting: usize,
+        /// Latency reported to the host right now
+        reported: usize,
+        sample_rate: u32,
+    },
+    UnderrunBehaviour(UnderrunBehaviour),
+    Alignment(AlignmentMode),
+    Underruns(usize),
+    /// What has happened on our channel, if we have one
+    ChannelStats(Option<ChannelStats>),
+    /// How far from our sample rate we are reading our channel, in parts per million
+    Drift(f64),
+    Param(Param, f32),
+    /// Host tempo in beats per minute
+    Tempo(f32),
+    /// Whether the safety limiter has recently had to step in
+    Protection(bool),
+    /// How the last preset export or import went
+    PresetStatus(String),
+}
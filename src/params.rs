@@ -0,0 +1,327 @@
+use std::collections::BTreeMap;
+
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+
+/// Parameter values coming from the host are integers in `0..=MAX_VALUE`
+pub const MAX_VALUE: i32 = 65536;
+
+/// Gain parameters cover this range, with the very bottom being silence
+const MIN_GAIN_DB: f32 = -60.0;
+const MAX_GAIN_DB: f32 = 12.0;
+
+/// Range the safety limiter ceiling can be set over
+const MIN_CEILING_DB: f32 = -24.0;
+const MAX_CEILING_DB: f32 = 0.0;
+
+/// Longest delay that can be set in milliseconds
+const MAX_DELAY_MS: f32 = 2000.0;
+/// Length of the receive side's delay line, and so the longest delay it can give.
+/// Delays set longer than this, like a whole note at a slow tempo, are cut down to it.
+pub const MAX_DELAY_SAMPLES: usize = 1 << 18;
+
+/// How the delay parameter is measured
+#[derive(Debug, PartialEq, Display, Clone, Copy, Eq, Serialize, Deserialize)]
+pub enum DelayUnit {
+    #[display(fmt = "ms")]
+    Milliseconds,
+    #[display(fmt = "samples")]
+    Samples,
+    /// Note divisions at the host tempo
+    #[display(fmt = "sync")]
+    Sync,
+}
+
+impl DelayUnit {
+    pub const ALL: [DelayUnit; 3] = [DelayUnit::Milliseconds, DelayUnit::Samples, DelayUnit::Sync];
+
+    fn from_normalised(value: f32) -> DelayUnit {
+        Self::ALL[normalised_to_step(value, Self::ALL.len())]
+    }
+
+    pub fn to_normalised(self) -> f32 {
+        step_to_normalised(self as usize, Self::ALL.len())
+    }
+}
+
+/// Note divisions the delay can sync to, as a name and a length in beats
+const DIVISIONS: [(&str, f32); 13] = [
+    ("1/64", 1.0 / 16.0),
+    ("1/32", 1.0 / 8.0),
+    ("1/16T", 1.0 / 6.0),
+    ("1/16", 1.0 / 4.0),
+    ("1/16D", 3.0 / 8.0),
+    ("1/8T", 1.0 / 3.0),
+    ("1/8", 1.0 / 2.0),
+    ("1/8D", 3.0 / 4.0),
+    ("1/4T", 2.0 / 3.0),
+    ("1/4", 1.0),
+    ("1/4D", 3.0 / 2.0),
+    ("1/2", 2.0),
+    ("1/1", 4.0),
+];
+
+/// Every parameter the host can see and automate, in the order the host sees them.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Display, Clone, Copy, Serialize, Deserialize)]
+pub enum Param {
+    #[display(fmt = "Send gain")]
+    SendGain,
+    #[display(fmt = "Receive gain")]
+    ReceiveGain,
+    #[display(fmt = "Dry/wet")]
+    Mix,
+    #[display(fmt = "Mute")]
+    Mute,
+    #[display(fmt = "Invert polarity")]
+    Invert,
+    #[display(fmt = "Limiter ceiling")]
+    Ceiling,
+    #[display(fmt = "Delay unit")]
+    DelayUnit,
+    #[display(fmt = "Delay")]
+    Delay,
+}
+
+impl Param {
+    pub const ALL: [Param; 8] = [
+        Param::SendGain,
+        Param::ReceiveGain,
+        Param::Mix,
+        Param::Mute,
+        Param::Invert,
+        Param::Ceiling,
+        Param::DelayUnit,
+        Param::Delay,
+    ];
+
+    pub fn from_index(index: usize) -> Option<Param> {
+        Self::ALL.get(index).copied()
+    }
+
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    /// Normalised (0..=1) value the parameter starts at
+    pub fn default_value(&self) -> f32 {
+        match self {
+            Param::SendGain | Param::ReceiveGain => db_to_normalised(0.0),
+            // NOTE(emily): Half and half, so a new return doesn't cut out the track it is put on
+            Param::Mix => 0.5,
+            Param::Mute | Param::Invert => 0.0,
+            Param::Ceiling => ceiling_db_to_normalised(-0.3),
+            Param::DelayUnit => DelayUnit::Milliseconds.to_normalised(),
+            Param::Delay => 0.0,
+        }
+    }
+
+    pub fn is_toggle(&self) -> bool {
+        matches!(self, Param::Mute | Param::Invert)
+    }
+
+    /// Human readable version of a normalised value. Some parameters depend on
+    /// others, or on the host, to make sense of, so this takes those too.
+    pub fn format(&self, value: f32, params: &Params, sample_rate: u32, tempo: f32) -> String {
+        match self {
+            Param::SendGain | Param::ReceiveGain => {
+                if normalised_to_gain(value) == 0.0 {
+                    "-inf dB".into()
+                } else {
+                    format!("{:.1} dB", normalised_to_db(value))
+                }
+            }
+            Param::Mix => format!("{:.0}%", value * 100.0),
+            Param::Mute | Param::Invert => {
+                if value >= 0.5 {
+                    "On".into()
+                } else {
+                    "Off".into()
+                }
+            }
+            Param::Ceiling => format!("{:.1} dB", normalised_to_ceiling_db(value)),
+            Param::DelayUnit => DelayUnit::from_normalised(value).to_string(),
+            Param::Delay => {
+                let unit = params.delay_unit();
+                let text = match unit {
+                    DelayUnit::Milliseconds => format!("{:.1} ms", value * MAX_DELAY_MS),
+                    DelayUnit::Samples => {
+                        format!("{:.0} samples", value * MAX_DELAY_SAMPLES as f32)
+                    }
+                    DelayUnit::Sync => DIVISIONS[normalised_to_step(value, DIVISIONS.len())]
+                        .0
+                        .into(),
+                };
+
+                if delay_to_samples(unit, value, sample_rate, tempo) > MAX_DELAY_SAMPLES as f32 {
+                    let longest = MAX_DELAY_SAMPLES as f32 * 1000.0 / sample_rate.max(1) as f32;
+                    format!("{} (cut to {:.0} ms)", text, longest)
+                } else {
+                    text
+                }
+            }
+        }
+    }
+}
+
+/// Length of a delay in samples, before it is cut down to fit the delay line
+fn delay_to_samples(unit: DelayUnit, value: f32, sample_rate: u32, tempo: f32) -> f32 {
+    match unit {
+        DelayUnit::Milliseconds => value * MAX_DELAY_MS * sample_rate as f32 / 1000.0,
+        DelayUnit::Samples => value * MAX_DELAY_SAMPLES as f32,
+        DelayUnit::Sync => {
+            let beats = DIVISIONS[normalised_to_step(value, DIVISIONS.len())].1;
+            beats * 60.0 / tempo.max(1.0) * sample_rate as f32
+        }
+    }
+}
+
+fn db_to_normalised(db: f32) -> f32 {
+    (db - MIN_GAIN_DB) / (MAX_GAIN_DB - MIN_GAIN_DB)
+}
+
+fn normalised_to_db(value: f32) -> f32 {
+    let db = MIN_GAIN_DB + value * (MAX_GAIN_DB - MIN_GAIN_DB);
+
+    // NOTE(emily): 0dB doesn't land exactly on a normalised value, snap to it so that
+    // a channel at unity gain passes samples through untouched.
+    if db.abs() < 0.001 {
+        0.0
+    } else {
+        db
+    }
+}
+
+/// Linear gain for a normalised gain parameter
+pub fn normalised_to_gain(value: f32) -> f32 {
+    if value <= 0.0 {
+        0.0
+    } else {
+        10f32.powf(normalised_to_db(value) / 20.0)
+    }
+}
+
+fn normalised_to_step(value: f32, steps: usize) -> usize {
+    ((value * (steps - 1) as f32).round() as usize).min(steps - 1)
+}
+
+fn step_to_normalised(step: usize, steps: usize) -> f32 {
+    step as f32 / (steps - 1) as f32
+}
+
+fn ceiling_db_to_normalised(db: f32) -> f32 {
+    (db - MIN_CEILING_DB) / (MAX_CEILING_DB - MIN_CEILING_DB)
+}
+
+fn normalised_to_ceiling_db(value: f32) -> f32 {
+    MIN_CEILING_DB + value * (MAX_CEILING_DB - MIN_CEILING_DB)
+}
+
+/// Current value of every parameter, normalised to 0..=1.
+/// Saved as a map of parameters to values so that adding parameters later
+/// doesn't break anything saved before.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(from = "BTreeMap<Param, f32>", into = "BTreeMap<Param, f32>")]
+pub struct Params([f32; Param::ALL.len()]);
+
+impl From<BTreeMap<Param, f32>> for Params {
+    fn from(values: BTreeMap<Param, f32>) -> Self {
+        let mut params = Params::default();
+        for (param, value) in values {
+            params.set(param, value);
+        }
+        params
+    }
+}
+
+impl From<Params> for BTreeMap<Param, f32> {
+    fn from(params: Params) -> Self {
+        Param::ALL.iter().map(|&p| (p, params.get(p))).collect()
+    }
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Self(Param::ALL.map(|p| p.default_value()))
+    }
+}
+
+impl Params {
+    pub fn get(&self, param: Param) -> f32 {
+        self.0[param.index()]
+    }
+
+    pub fn set(&mut self, param: Param, value: f32) {
+        self.0[param.index()] = value.clamp(0.0, 1.0);
+    }
+
+    pub fn is_on(&self, param: Param) -> bool {
+        self.get(param) >= 0.5
+    }
+
+    /// Gain applied to whatever goes through the channel, with mute and polarity included
+    pub fn routed_gain(&self, gain: Param) -> f32 {
+        let gain = normalised_to_gain(self.get(gain));
+        let gain = if self.is_on(Param::Invert) {
+            -gain
+        } else {
+            gain
+        };
+
+        if self.is_on(Param::Mute) {
+            0.0
+        } else {
+            gain
+        }
+    }
+
+    /// Linear peak the safety limiter holds the received signal under
+    pub fn ceiling(&self) -> f32 {
+        10f32.powf(normalised_to_ceiling_db(self.get(Param::Ceiling)) / 20.0)
+    }
+
+    pub fn delay_unit(&self) -> DelayUnit {
+        DelayUnit::from_normalised(self.get(Param::DelayUnit))
+    }
+
+    /// Length of the delay on the receive side, in samples, cut down to what the
+    /// delay line can give
+    pub fn delay_samples(&self, sample_rate: u32, tempo: f32) -> f32 {
+        delay_to_samples(
+            self.delay_unit(),
+            self.get(Param::Delay),
+            sample_rate,
+            tempo,
+        )
+        .min(MAX_DELAY_SAMPLES as f32)
+    }
+
+    pub fn to_host(value: f32) -> i32 {
+        (value * MAX_VALUE as f32).round() as i32
+    }
+
+    pub fn from_host(value: i32) -> f32 {
+        value as f32 / MAX_VALUE as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_delays_are_cut_to_the_line() {
+        let mut params = Params::default();
+        params.set(Param::DelayUnit, DelayUnit::Sync.to_normalised());
+        params.set(Param::Delay, 1.0);
+
+        // NOTE(emily): A whole note at 20 BPM is 12 seconds, far longer than the line.
+        assert_eq!(params.delay_samples(48000, 20.0), MAX_DELAY_SAMPLES as f32);
+        assert!(Param::Delay
+            .format(1.0, &params, 48000, 20.0)
+            .contains("cut to 5461 ms"));
+
+        // NOTE(emily): At 120 BPM it is two seconds and fits.
+        assert_eq!(params.delay_samples(48000, 120.0), 96000.0);
+        assert_eq!(Param::Delay.format(1.0, &params, 48000, 120.0), "1/1");
+    }
+}
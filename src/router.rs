@@ -1,144 +1,1258 @@
 use std::{
-    collections::HashMap,
-    sync::{mpsc, Arc, Weak},
+    cell::UnsafeCell,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use derive_more::Deref;
-use parking_lot::{MappedMutexGuard, Mutex, MutexGuard};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
 use shared_memory::Shmem;
 use uuid::Uuid;
 
-use crate::Sample;
+use crate::{
+    layout::{Frame, Layout, MAX_WIDTH},
+    Mode,
+};
+
+pub mod ring;
+
+use ring::{SampleRing, WideLanes, LANE_WIDTH, MAX_SENDERS};
+
+pub use ring::{Located, Stamp, Timing};
+
+/// Bump this whenever a release changes the layout of [`Registry`]. Instances
+/// with different versions will not see each other's channels.
+pub const VERSION: u32 = 1;
+
+/// Name of the shared memory segment every instance on this machine opens
+pub fn segment_name() -> String {
+    format!("emilydotgg-feedback-router-v{}", VERSION)
+}
+
+/// Maximum number of channels that can exist at once
+pub const MAX_CHANNELS: usize = 32;
+
+/// Maximum number of channels that can carry more than stereo at once
+pub const MAX_WIDE_CHANNELS: usize = 4;
+
+/// Longest channel name (in bytes) that we keep
+pub const MAX_NAME_LEN: usize = 64;
+
+/// Maximum number of instances that can be listed at once
+pub const MAX_INSTANCES: usize = 64;
+
+/// Longest instance label (in bytes) that we keep
+pub const MAX_LABEL_LEN: usize = 64;
+
+/// How long an instance can go without announcing itself, or a sender or receiver
+/// without saying it is still attached, before everyone else assumes it went away
+/// without saying so
+const INSTANCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+const MAGIC: u32 = u32::from_le_bytes(*b"EDGF");
+
+const FREE: u32 = 0;
+const ACTIVE: u32 = 1;
+
+const HAS_COLOUR: u32 = 1 << 24;
+
+/// How long to wait for another process to finish setting up the registry, or
+/// to release the registry lock, before giving up on it.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub type Colour = [u8; 3];
+
+/// Everything about a channel that a person cares about
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelInfo {
+    pub id: Uuid,
+    pub name: String,
+    pub colour: Option<Colour>,
+    pub layout: Layout,
+}
 
-pub struct Channels<T>(mpsc::SyncSender<T>, mpsc::Receiver<T>);
+/// A channel as it was when a project was saved, with enough to tell whether
+/// it is newer than a channel that already exists.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelSnapshot {
+    pub info: ChannelInfo,
+    /// Milliseconds since the unix epoch when the name, colour or layout last changed
+    pub modified: u64,
+}
 
-impl<T> Channels<T> {
-    fn new() -> Self {
-        let (tx, rx) = mpsc::sync_channel::<T>(10);
+/// A channel as it was when a project was saved, along with every instance in
+/// the project that was on it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraphChannel {
+    pub channel: ChannelSnapshot,
+    pub members: Vec<(Uuid, Mode)>,
+}
+
+/// An instance, as everybody else sees it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceInfo {
+    pub id: Uuid,
+    /// Whatever the user calls this instance, e.g. the mixer track it is on
+    pub label: String,
+    pub mode: Mode,
+    pub channel: Option<Uuid>,
+    /// Id of the process the instance lives in
+    pub process: u32,
+}
 
-        Self(tx, rx)
+impl Display for InstanceInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.label.is_empty() {
+            write!(f, "{}", &self.id.simple().to_string()[..8])
+        } else {
+            write!(f, "{}", self.label)
+        }
     }
 }
 
-type SamplesChannels = Channels<Vec<Sample>>;
+impl Display for ChannelInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.name.is_empty() {
+            write!(f, "{}", self.id)
+        } else {
+            write!(f, "{}", self.name)
+        }
+    }
+}
 
-struct _Router {
-    channels: HashMap<Uuid, SamplesChannels>,
+/// What has happened on a channel since it was made
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelStats {
+    /// Blocks written by senders
+    pub blocks_sent: u64,
+    /// Blocks that never reached a receiver because the channel had no room for them
+    pub blocks_dropped: u64,
+    /// Blocks a receiver needed before they had been sent
+    pub underruns: u64,
+    /// Blocks a receiver skipped over without reading, going by their sequence numbers
+    pub blocks_missed: u64,
+    /// Blocks a receiver read more than once
+    pub blocks_repeated: u64,
+    /// Times a sender's song position jumped, e.g. because the host seeked
+    pub transport_jumps: u64,
+    /// Times an instance couldn't join because the channel was full
+    pub turned_away: u64,
+    /// Samples waiting for whichever receiver is furthest behind
+    pub fill: usize,
+    /// Most samples that have ever been waiting for a receiver
+    pub high_water: usize,
+    /// Rate the channel is being sent at, if anyone has sent anything yet
+    pub sample_rate: Option<u32>,
 }
 
-impl _Router {
-    fn new() -> Self {
-        Self {
-            channels: Default::default(),
+/// Running totals behind [`ChannelStats`], shared by everyone attached to a channel
+#[repr(C)]
+struct Counters {
+    blocks_sent: AtomicU64,
+    blocks_dropped: AtomicU64,
+    underruns: AtomicU64,
+    blocks_missed: AtomicU64,
+    blocks_repeated: AtomicU64,
+    transport_jumps: AtomicU64,
+    turned_away: AtomicU64,
+    high_water: AtomicU64,
+}
+
+impl Counters {
+    fn reset(&self) {
+        self.blocks_sent.store(0, Ordering::Relaxed);
+        self.blocks_dropped.store(0, Ordering::Relaxed);
+        self.underruns.store(0, Ordering::Relaxed);
+        self.blocks_missed.store(0, Ordering::Relaxed);
+        self.blocks_repeated.store(0, Ordering::Relaxed);
+        self.transport_jumps.store(0, Ordering::Relaxed);
+        self.turned_away.store(0, Ordering::Relaxed);
+        self.high_water.store(0, Ordering::Relaxed);
+    }
+}
+
+/// A single channel, acting as a bus that any number of senders mix into and
+/// any number of receivers read from.
+#[repr(C)]
+pub struct Channel {
+    state: AtomicU32,
+    /// Bumped every time the channel is deleted, so that anyone still holding on
+    /// to it can tell it went away
+    generation: AtomicU32,
+    id: [AtomicU64; 2],
+    /// Only touched while holding the registry lock
+    name: UnsafeCell<[u8; MAX_NAME_LEN]>,
+    name_len: AtomicU32,
+    /// 0x00RRGGBB, or'd with HAS_COLOUR if the channel has a colour at all
+    colour: AtomicU32,
+    /// [`Layout::to_bits`] of what the channel carries
+    layout: AtomicU32,
+    /// One more than the index of the [`WideLanes`] this channel keeps anything past
+    /// stereo in, or 0 if it has never been wider than stereo
+    wide: AtomicU32,
+    /// Milliseconds since the unix epoch when the name, colour or layout last changed
+    modified: AtomicU64,
+    /// Seconds since the unix epoch since which nobody has been attached to this
+    /// channel, or 0 if somebody is
+    unused_since: AtomicU64,
+    /// Rate senders are running at, or 0 if nobody has sent anything yet
+    sample_rate: AtomicU32,
+    counters: Counters,
+    ring: SampleRing,
+}
+
+fn load_uuid(id: &[AtomicU64; 2]) -> Uuid {
+    Uuid::from_u64_pair(id[0].load(Ordering::Relaxed), id[1].load(Ordering::Relaxed))
+}
+
+fn store_uuid(id: &[AtomicU64; 2], uuid: &Uuid) {
+    let (hi, lo) = uuid.as_u64_pair();
+    id[0].store(hi, Ordering::Relaxed);
+    id[1].store(lo, Ordering::Relaxed);
+}
+
+/// Copy as much of `text` as fits into `stored`, returning how many bytes that was
+fn store_str(stored: &mut [u8], text: &str) -> usize {
+    // NOTE(emily): Cut long text down on a char boundary so we always store valid utf8
+    let mut len = text.len().min(stored.len());
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    stored[..len].copy_from_slice(&text.as_bytes()[..len]);
+    len
+}
+
+fn load_str(stored: &[u8], len: &AtomicU32) -> String {
+    let len = (len.load(Ordering::Relaxed) as usize).min(stored.len());
+    String::from_utf8_lossy(&stored[..len]).into_owned()
+}
+
+impl Channel {
+    fn id(&self) -> Uuid {
+        load_uuid(&self.id)
+    }
+
+    fn is_active(&self) -> bool {
+        self.state.load(Ordering::Acquire) == ACTIVE
+    }
+
+    fn colour(&self) -> Option<Colour> {
+        let colour = self.colour.load(Ordering::Relaxed);
+        if colour & HAS_COLOUR == 0 {
+            return None;
         }
+
+        let [_, r, g, b] = colour.to_be_bytes();
+        Some([r, g, b])
     }
 
-    fn new_channel(&mut self) -> Uuid {
-        let new_uuid = Uuid::new_v4();
-        self.channels.insert(new_uuid, Channels::new());
-        new_uuid
+    fn set_colour(&self, colour: Option<Colour>) {
+        let colour = colour
+            .map(|[r, g, b]| HAS_COLOUR | u32::from_be_bytes([0, r, g, b]))
+            .unwrap_or(0);
+        self.colour.store(colour, Ordering::Relaxed);
+    }
+
+    fn info(&self, _guard: &RegistryGuard) -> ChannelInfo {
+        // SAFETY: We hold the registry lock
+        let name = unsafe { &*self.name.get() };
+
+        ChannelInfo {
+            id: self.id(),
+            name: load_str(name, &self.name_len),
+            colour: self.colour(),
+            layout: self.layout(),
+        }
+    }
+
+    pub fn layout(&self) -> Layout {
+        Layout::from_bits(self.layout.load(Ordering::Relaxed))
+    }
+
+    fn set_layout(&self, layout: Layout) {
+        self.layout.store(layout.to_bits(), Ordering::Relaxed);
+    }
+
+    fn snapshot(&self, guard: &RegistryGuard) -> ChannelSnapshot {
+        ChannelSnapshot {
+            info: self.info(guard),
+            modified: self.modified.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn stats(&self) -> ChannelStats {
+        ChannelStats {
+            blocks_sent: self.counters.blocks_sent.load(Ordering::Relaxed),
+            blocks_dropped: self.counters.blocks_dropped.load(Ordering::Relaxed),
+            underruns: self.counters.underruns.load(Ordering::Relaxed),
+            blocks_missed: self.counters.blocks_missed.load(Ordering::Relaxed),
+            blocks_repeated: self.counters.blocks_repeated.load(Ordering::Relaxed),
+            transport_jumps: self.counters.transport_jumps.load(Ordering::Relaxed),
+            turned_away: self.counters.turned_away.load(Ordering::Relaxed),
+            fill: self.ring.fill(),
+            high_water: self.counters.high_water.load(Ordering::Relaxed) as usize,
+            sample_rate: self.sample_rate(),
+        }
+    }
+
+    fn sample_rate(&self) -> Option<u32> {
+        match self.sample_rate.load(Ordering::Relaxed) {
+            0 => None,
+            rate => Some(rate),
+        }
+    }
+
+    /// Count a block that somebody meant to send or receive but that was lost
+    pub fn record_dropped(&self) {
+        self.counters.blocks_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an instance that wanted to join but found no room
+    pub fn record_turned_away(&self) {
+        self.counters.turned_away.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_underrun(&self) {
+        self.counters.underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn set_name(&self, _guard: &RegistryGuard, name: &str) {
+        // SAFETY: We hold the registry lock
+        let len = store_str(unsafe { &mut *self.name.get() }, name);
+        self.name_len.store(len as u32, Ordering::Relaxed);
+    }
+}
+
+/// Somewhere for an instance to say who it is and what it is connected to, so
+/// that every other instance can show it
+#[repr(C)]
+struct InstanceSlot {
+    state: AtomicU32,
+    id: [AtomicU64; 2],
+    /// Only touched while holding the registry lock
+    label: UnsafeCell<[u8; MAX_LABEL_LEN]>,
+    label_len: AtomicU32,
+    /// Index into [`Mode::ALL`]
+    mode: AtomicU32,
+    /// Nil if not on a channel
+    channel: [AtomicU64; 2],
+    process: AtomicU32,
+    /// Seconds since the unix epoch when this instance last announced itself
+    last_seen: AtomicU64,
+    /// Set when somebody else wants this instance on `requested_channel`
+    request: AtomicU32,
+    requested_channel: [AtomicU64; 2],
+}
+
+impl InstanceSlot {
+    fn is_active(&self) -> bool {
+        self.state.load(Ordering::Acquire) == ACTIVE
+    }
+
+    fn is_stale(&self, now: u64) -> bool {
+        now.saturating_sub(self.last_seen.load(Ordering::Relaxed)) >= INSTANCE_TIMEOUT.as_secs()
+    }
+
+    fn info(&self, _guard: &RegistryGuard) -> InstanceInfo {
+        // SAFETY: We hold the registry lock
+        let label = unsafe { &*self.label.get() };
+        let channel = load_uuid(&self.channel);
+
+        InstanceInfo {
+            id: load_uuid(&self.id),
+            label: load_str(label, &self.label_len),
+            mode: Mode::ALL
+                .get(self.mode.load(Ordering::Relaxed) as usize)
+                .copied()
+                .unwrap_or(Mode::Receiver),
+            channel: (!channel.is_nil()).then_some(channel),
+            process: self.process.load(Ordering::Relaxed),
+        }
+    }
+
+    fn update(&self, _guard: &RegistryGuard, info: &InstanceInfo) {
+        // SAFETY: We hold the registry lock
+        let len = store_str(unsafe { &mut *self.label.get() }, &info.label);
+        self.label_len.store(len as u32, Ordering::Relaxed);
+
+        let mode = Mode::ALL.iter().position(|m| *m == info.mode).unwrap_or(0);
+        self.mode.store(mode as u32, Ordering::Relaxed);
+        store_uuid(&self.channel, &info.channel.unwrap_or(Uuid::nil()));
+        self.process.store(info.process, Ordering::Relaxed);
+        self.last_seen.store(unix_time(), Ordering::Relaxed);
+    }
+}
+
+#[repr(C)]
+struct Header {
+    magic: u32,
+    version: u32,
+    size: u64,
+    /// Set once whoever created the registry has finished setting it up
+    ready: AtomicU32,
+    /// Held while adding or removing channels, or touching their names
+    lock: AtomicU32,
+}
+
+/// Everything that is shared between instances, laid out so that it can live
+/// in a shared memory segment and be used from several processes at once.
+#[repr(C)]
+struct Registry {
+    header: Header,
+    channels: [Channel; MAX_CHANNELS],
+    instances: [InstanceSlot; MAX_INSTANCES],
+    wide: [WideLanes; MAX_WIDE_CHANNELS],
+}
+
+struct RegistryGuard<'a>(&'a AtomicU32);
+
+impl Drop for RegistryGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(0, Ordering::Release);
+    }
+}
+
+impl Registry {
+    fn is_valid(&self) -> bool {
+        self.header.magic == MAGIC
+            && self.header.version == VERSION
+            && self.header.size == std::mem::size_of::<Registry>() as u64
+    }
+
+    /// Take the registry lock. This is only ever held for a moment, so if we cannot
+    /// get it in time then whoever had it probably died holding it and we take it anyway.
+    fn lock(&self) -> RegistryGuard<'_> {
+        let started = Instant::now();
+        while self
+            .header
+            .lock
+            .compare_exchange_weak(0, 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            if started.elapsed() > WAIT_TIMEOUT {
+                log::warn!("taking registry lock from unresponsive owner");
+                break;
+            }
+            std::thread::yield_now();
+        }
+
+        RegistryGuard(&self.header.lock)
+    }
+
+    fn find(&self, uuid: &Uuid) -> Option<usize> {
+        self.channels
+            .iter()
+            .position(|c| c.is_active() && c.id() == *uuid)
+    }
+
+    /// Add a channel with this id if it does not already exist, returning its index
+    /// and whether it was created.
+    fn insert(&self, guard: &RegistryGuard, uuid: &Uuid) -> Option<(usize, bool)> {
+        if let Some(index) = self.find(uuid) {
+            return Some((index, false));
+        }
+
+        let index = self.channels.iter().position(|c| !c.is_active())?;
+        let channel = &self.channels[index];
+
+        store_uuid(&channel.id, uuid);
+        channel.set_name(guard, "");
+        channel.set_colour(None);
+        channel.set_layout(Layout::Stereo);
+        channel.wide.store(0, Ordering::Release);
+        channel.modified.store(unix_time_ms(), Ordering::Relaxed);
+        channel.unused_since.store(0, Ordering::Relaxed);
+        channel.sample_rate.store(0, Ordering::Relaxed);
+        channel.counters.reset();
+        channel.ring.reset();
+        channel.state.store(ACTIVE, Ordering::Release);
+
+        Some((index, true))
+    }
+
+    fn find_instance(&self, id: &Uuid) -> Option<usize> {
+        self.instances
+            .iter()
+            .position(|i| i.is_active() && load_uuid(&i.id) == *id)
     }
 
-    fn new_channel_with_id(&mut self, uuid: &Uuid) {
-        self.channels.insert(uuid.clone(), Channels::new());
+    fn remove(&self, _guard: &RegistryGuard, index: usize) {
+        let channel = &self.channels[index];
+        channel.generation.fetch_add(1, Ordering::AcqRel);
+        channel.state.store(FREE, Ordering::Release);
+        channel.wide.store(0, Ordering::Release);
     }
 
-    fn channel(&mut self, uuid: &Uuid) -> Option<&mut SamplesChannels> {
-        self.channels.get_mut(uuid)
+    /// Room past stereo for this channel, if it has any
+    fn wide_lanes(&self, channel: &Channel) -> Option<&WideLanes> {
+        match channel.wide.load(Ordering::Acquire) {
+            0 => None,
+            index => self.wide.get(index as usize - 1),
+        }
     }
+
+    /// Change what a channel carries, finding it room past stereo if it needs it.
+    /// Returns false, leaving the channel as it was, if there is no room to be had.
+    fn set_layout(&self, _guard: &RegistryGuard, index: usize, layout: Layout) -> bool {
+        let channel = &self.channels[index];
+
+        if layout.width() > LANE_WIDTH && channel.wide.load(Ordering::Relaxed) == 0 {
+            let taken = |w: usize| {
+                self.channels
+                    .iter()
+                    .any(|c| c.is_active() && c.wide.load(Ordering::Relaxed) as usize == w + 1)
+            };
+            let Some(free) = (0..MAX_WIDE_CHANNELS).find(|&w| !taken(w)) else {
+                return false;
+            };
+
+            self.wide[free].clear();
+            channel.wide.store(free as u32 + 1, Ordering::Release);
+        }
+
+        // NOTE(emily): A channel made narrower again keeps its room, so that a sender
+        // part way through a block never ends up writing into somebody else's.
+        channel.set_layout(layout);
+        true
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+enum Memory {
+    Shared(Shmem),
+    Private(Box<Registry>),
 }
 
-pub struct Router(Mutex<_Router>);
+/// View onto the channel registry, either in shared memory or private to this process.
+pub struct Router {
+    registry: *const Registry,
+    _memory: Memory,
+}
+
+// NOTE(emily): Everything reachable through `registry` is atomics, and it is kept
+// alive by `_memory`.
+unsafe impl Send for Router {}
+unsafe impl Sync for Router {}
 
 impl Router {
+    /// Create a router that is private to this process
     pub fn new() -> Self {
-        Self(Mutex::new(_Router::new()))
+        // SAFETY: Registry is entirely atomics and plain integers, for which all zeroes is valid.
+        // NOTE(emily): This is too big to construct on the stack.
+        let mut registry = unsafe { Box::<Registry>::new_zeroed().assume_init() };
+        unsafe { Self::init(&mut *registry) };
+
+        Self {
+            registry: &*registry,
+            _memory: Memory::Private(registry),
+        }
     }
 
-    pub fn new_channel(&self) -> Uuid {
-        self.0.lock().new_channel()
+    /// Must only be called by whoever created the registry, before anyone else
+    /// can see it as ready.
+    unsafe fn init(registry: *mut Registry) {
+        let header = &mut (*registry).header;
+        header.magic = MAGIC;
+        header.version = VERSION;
+        header.size = std::mem::size_of::<Registry>() as u64;
+        header.ready.store(1, Ordering::Release);
     }
 
-    pub fn new_channel_with_id(&self, uuid: &Uuid) {
-        self.0.lock().new_channel_with_id(uuid)
+    /// Open the shared registry with this name, creating it if nobody has yet
+    pub fn open_shared(name: &str) -> eyre::Result<Self> {
+        let config = shared_memory::ShmemConf::new()
+            .size(std::mem::size_of::<Registry>())
+            .os_id(name);
+
+        let (mut memory, created) = match config.clone().create() {
+            Ok(memory) => (memory, true),
+            Err(_) => (config.open()?, false),
+        };
+
+        // NOTE(emily): Nobody owns the registry, it needs to outlive whichever
+        // process happened to create it.
+        memory.set_owner(false);
+
+        if memory.len() < std::mem::size_of::<Registry>() {
+            eyre::bail!("shared registry {} is too small", name);
+        }
+
+        let registry = memory.as_ptr() as *mut Registry;
+
+        // SAFETY: Memory is at least as big as the registry and is either freshly
+        // zeroed or set up by another instance.
+        unsafe {
+            if created {
+                Self::init(registry);
+            } else {
+                let started = Instant::now();
+                while (*registry).header.ready.load(Ordering::Acquire) == 0 {
+                    if started.elapsed() > WAIT_TIMEOUT {
+                        eyre::bail!("shared registry {} was never set up", name);
+                    }
+                    std::thread::yield_now();
+                }
+            }
+
+            if !(*registry).is_valid() {
+                eyre::bail!("shared registry {} has an incompatible layout", name);
+            }
+        }
+
+        Ok(Self {
+            registry,
+            _memory: Memory::Shared(memory),
+        })
     }
 
-    pub fn channel(&self, uuid: &Uuid) -> Option<MappedMutexGuard<SamplesChannels>> {
-        MutexGuard::try_map(self.0.lock(), |s| s.channel(uuid)).ok()
+    fn registry(&self) -> &Registry {
+        // SAFETY: See above
+        unsafe { &*self.registry }
     }
 
-    pub fn rx(&self, uuid: &Uuid) -> Option<MappedMutexGuard<mpsc::Receiver<Vec<Sample>>>> {
+    pub fn new_channel(&self) -> Option<Uuid> {
+        let new_uuid = Uuid::new_v4();
+        let registry = self.registry();
+        let guard = registry.lock();
+        registry.insert(&guard, &new_uuid).map(|_| new_uuid)
+    }
+
+    /// Create a channel with this id, if it doesn't already exist.
+    /// Returns false if there is no room for any more channels.
+    pub fn new_channel_with_id(&self, uuid: &Uuid) -> bool {
+        let registry = self.registry();
+        let guard = registry.lock();
+        registry.insert(&guard, uuid).is_some()
+    }
+
+    /// Create a channel from a snapshot, if it doesn't already exist. If it does
+    /// then whichever of the two was changed last decides what it is called, so
+    /// it doesn't matter what order snapshots are restored in.
+    /// Returns false if there is no room for any more channels.
+    pub fn restore_channel(&self, snapshot: &ChannelSnapshot) -> bool {
+        let registry = self.registry();
+        let guard = registry.lock();
+
+        match registry.insert(&guard, &snapshot.info.id) {
+            Some((index, created)) => {
+                let channel = &registry.channels[index];
+                if created || snapshot.modified > channel.modified.load(Ordering::Relaxed) {
+                    channel.set_name(&guard, &snapshot.info.name);
+                    channel.set_colour(snapshot.info.colour);
+                    if !registry.set_layout(&guard, index, snapshot.info.layout) {
+                        log::warn!(
+                            "no room for {} to carry {}, it stays {}",
+                            snapshot.info.id,
+                            snapshot.info.layout,
+                            channel.layout()
+                        );
+                    }
+                    channel.modified.store(snapshot.modified, Ordering::Relaxed);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn channel_snapshot(&self, uuid: &Uuid) -> Option<ChannelSnapshot> {
+        let registry = self.registry();
+        let guard = registry.lock();
+        registry
+            .find(uuid)
+            .map(|index| registry.channels[index].snapshot(&guard))
+    }
+
+    /// Snapshot every channel that instances in `process` are on, along with
+    /// which of them are on it.
+    pub fn graph(&self, process: u32) -> Vec<GraphChannel> {
+        let registry = self.registry();
+        let guard = registry.lock();
+        let now = unix_time();
+
+        let mut graph: Vec<GraphChannel> = vec![];
+        let members = registry
+            .instances
+            .iter()
+            .filter(|i| i.is_active() && !i.is_stale(now))
+            .map(|i| i.info(&guard))
+            .filter(|i| i.process == process);
+
+        for member in members {
+            let Some(channel) = member.channel else {
+                continue;
+            };
+
+            match graph.iter_mut().find(|g| g.channel.info.id == channel) {
+                Some(entry) => entry.members.push((member.id, member.mode)),
+                None => {
+                    if let Some(index) = registry.find(&channel) {
+                        graph.push(GraphChannel {
+                            channel: registry.channels[index].snapshot(&guard),
+                            members: vec![(member.id, member.mode)],
+                        });
+                    }
+                }
+            }
+        }
+
+        graph
+    }
+
+    pub fn channel(&self, uuid: &Uuid) -> Option<&Channel> {
+        let registry = self.registry();
+        registry.find(uuid).map(|index| &registry.channels[index])
+    }
+
+    pub fn channel_stats(&self, uuid: &Uuid) -> Option<ChannelStats> {
+        self.channel(uuid).map(Channel::stats)
+    }
+
+    pub fn channel_info(&self, uuid: &Uuid) -> Option<ChannelInfo> {
+        let registry = self.registry();
+        let guard = registry.lock();
+        registry
+            .find(uuid)
+            .map(|index| registry.channels[index].info(&guard))
+    }
+
+    pub fn rename_channel(&self, uuid: &Uuid, name: &str) -> bool {
+        let registry = self.registry();
+        let guard = registry.lock();
+        registry
+            .find(uuid)
+            .map(|index| {
+                let channel = &registry.channels[index];
+                channel.set_name(&guard, name);
+                channel.modified.store(unix_time_ms(), Ordering::Relaxed);
+            })
+            .is_some()
+    }
+
+    pub fn set_channel_colour(&self, uuid: &Uuid, colour: Option<Colour>) -> bool {
         self.channel(uuid)
-            .map(|c| MappedMutexGuard::map(c, |o| &mut o.1))
+            .map(|c| {
+                c.set_colour(colour);
+                c.modified.store(unix_time_ms(), Ordering::Relaxed);
+            })
+            .is_some()
     }
 
-    pub fn tx(&self, uuid: &Uuid) -> Option<mpsc::SyncSender<Vec<Sample>>> {
-        self.channel(uuid).map(|c| c.0.clone())
+    /// Change what a channel carries. Senders and receivers already on it mix
+    /// to and from the new layout from their next block. Returns false if the
+    /// channel doesn't exist, or it would be one more than [`MAX_WIDE_CHANNELS`]
+    /// channels wider than stereo.
+    pub fn set_channel_layout(&self, uuid: &Uuid, layout: Layout) -> bool {
+        let registry = self.registry();
+        let guard = registry.lock();
+        let Some(index) = registry.find(uuid) else {
+            return false;
+        };
+
+        let changed = registry.set_layout(&guard, index, layout);
+        if changed {
+            registry.channels[index]
+                .modified
+                .store(unix_time_ms(), Ordering::Relaxed);
+        }
+        changed
     }
 
-    pub fn ids(&self) -> Vec<Uuid> {
-        self.0.lock().channels.keys().map(|k| *k).collect()
+    /// Delete a channel. Anyone still attached to it will find their sender or
+    /// receiver no longer alive.
+    pub fn delete_channel(&self, uuid: &Uuid) -> bool {
+        let registry = self.registry();
+        let guard = registry.lock();
+        registry
+            .find(uuid)
+            .map(|index| registry.remove(&guard, index))
+            .is_some()
     }
-}
 
-pub struct _SharedRouter(Router, Option<Shmem>);
+    /// Delete every channel that nobody has been attached to for at least `unused_for`,
+    /// returning the ids of the channels that were deleted.
+    pub fn collect_garbage(&self, unused_for: Duration) -> Vec<Uuid> {
+        let registry = self.registry();
+        let guard = registry.lock();
+        let now = unix_time();
+        let mut removed = vec![];
+
+        for (index, channel) in registry.channels.iter().enumerate() {
+            if !channel.is_active() {
+                continue;
+            }
+
+            // Free up room held by senders and receivers whose process died
+            let reclaimed = channel
+                .ring
+                .reclaim(now.saturating_sub(INSTANCE_TIMEOUT.as_secs()));
+            if reclaimed > 0 {
+                log::warn!(
+                    "reclaimed {} senders and receivers that went away from {}",
+                    reclaimed,
+                    channel.id()
+                );
+            }
+
+            if channel.ring.is_in_use() {
+                channel.unused_since.store(0, Ordering::Relaxed);
+                continue;
+            }
+
+            let unused_since = channel.unused_since.load(Ordering::Relaxed);
+            if unused_since == 0 {
+                channel.unused_since.store(now, Ordering::Relaxed);
+            } else if now.saturating_sub(unused_since) >= unused_for.as_secs() {
+                removed.push(channel.id());
+                registry.remove(&guard, index);
+            }
+        }
+
+        // Free up room held by instances that went away without withdrawing
+        for instance in registry.instances.iter() {
+            if instance.is_active() && instance.is_stale(now) {
+                instance.state.store(FREE, Ordering::Release);
+            }
+        }
+
+        removed
+    }
+
+    /// Tell everyone else who we are and what we are connected to. Needs doing
+    /// at least every [`INSTANCE_TIMEOUT`] to stay listed.
+    /// Returns false if there is no room for any more instances.
+    pub fn announce(&self, info: &InstanceInfo) -> bool {
+        let registry = self.registry();
+        let guard = registry.lock();
 
-impl std::ops::Deref for _SharedRouter {
-    type Target = Router;
+        let index = match registry.find_instance(&info.id) {
+            Some(index) => index,
+            None => {
+                let Some(index) = registry.instances.iter().position(|i| !i.is_active()) else {
+                    return false;
+                };
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+                let instance = &registry.instances[index];
+                store_uuid(&instance.id, &info.id);
+                instance.request.store(0, Ordering::Relaxed);
+                index
+            }
+        };
+
+        let instance = &registry.instances[index];
+        instance.update(&guard, info);
+        instance.state.store(ACTIVE, Ordering::Release);
+        true
     }
-}
-impl _SharedRouter {}
 
-impl Drop for _SharedRouter {
-    fn drop(&mut self) {
-        // We were dropped (last person holding the shared memory)
-        // Now we need to clear ourselves up
-        if let Some(mut shmem) = self.1.take() {
-            shmem.set_owner(true);
-            let zelf_box =
-                unsafe { Weak::from_raw(*(shmem.as_ptr() as *mut *const _SharedRouter)) };
-            drop(zelf_box);
-            drop(shmem)
+    /// Stop being listed
+    pub fn withdraw(&self, id: &Uuid) {
+        let registry = self.registry();
+        let _guard = registry.lock();
+        if let Some(index) = registry.find_instance(id) {
+            registry.instances[index]
+                .state
+                .store(FREE, Ordering::Release);
         }
     }
+
+    /// Every instance that has announced itself recently
+    pub fn instances(&self) -> Vec<InstanceInfo> {
+        let registry = self.registry();
+        let guard = registry.lock();
+        let now = unix_time();
+        registry
+            .instances
+            .iter()
+            .filter(|i| i.is_active() && !i.is_stale(now))
+            .map(|i| i.info(&guard))
+            .collect()
+    }
+
+    /// Ask an instance to move to another channel, or off its channel altogether.
+    /// It is up to that instance to pick this up with [`Router::take_channel_request`].
+    pub fn request_channel(&self, id: &Uuid, channel: Option<Uuid>) -> bool {
+        let registry = self.registry();
+        let _guard = registry.lock();
+        registry
+            .find_instance(id)
+            .map(|index| {
+                let instance = &registry.instances[index];
+                store_uuid(&instance.requested_channel, &channel.unwrap_or(Uuid::nil()));
+                instance.request.store(1, Ordering::Release);
+            })
+            .is_some()
+    }
+
+    /// Where somebody else last asked us to move to, if anywhere
+    pub fn take_channel_request(&self, id: &Uuid) -> Option<Option<Uuid>> {
+        let registry = self.registry();
+        let instance = &registry.instances[registry.find_instance(id)?];
+        if instance.request.swap(0, Ordering::Acquire) == 0 {
+            return None;
+        }
+
+        let channel = load_uuid(&instance.requested_channel);
+        Some((!channel.is_nil()).then_some(channel))
+    }
+
+    pub fn channels(&self) -> Vec<ChannelInfo> {
+        let registry = self.registry();
+        let guard = registry.lock();
+        registry
+            .channels
+            .iter()
+            .filter(|c| c.is_active())
+            .map(|c| c.info(&guard))
+            .collect()
+    }
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[derive(Deref, Clone)]
-pub struct SharedRouter(Arc<_SharedRouter>);
+pub struct SharedRouter(Arc<Router>);
 
 impl SharedRouter {
+    /// Open the registry shared between every instance on this machine. If that isn't
+    /// possible, fall back to one that is only shared within this process.
     pub fn new_or_open(name: &str) -> SharedRouter {
-        let config = shared_memory::ShmemConf::new()
-            .size(std::mem::size_of::<*mut *const _SharedRouter>())
-            .os_id(name);
-        let open_config = config.clone();
-        if let Ok(memory) = config.create() {
-            let mem_ptr = memory.as_ptr();
+        static FALLBACK: Mutex<Weak<Router>> = Mutex::new(Weak::new());
 
-            let inner = Arc::new(_SharedRouter(Router::new(), Some(memory)));
-            let weak = Arc::downgrade(&inner);
+        match Router::open_shared(name) {
+            Ok(router) => SharedRouter(Arc::new(router)),
+            Err(err) => {
+                log::error!("unable to open shared router, using a local one: {}", err);
 
-            unsafe {
-                *std::mem::transmute::<*mut u8, *mut *const _SharedRouter>(mem_ptr) =
-                    weak.into_raw();
+                let mut fallback = FALLBACK.lock();
+                let router = fallback
+                    .upgrade()
+                    .unwrap_or_else(|| Arc::new(Router::new()));
+                *fallback = Arc::downgrade(&router);
+                SharedRouter(router)
             }
+        }
+    }
 
-            SharedRouter(inner)
+    /// A registry that nobody else can see, e.g. for tests
+    pub fn new_private() -> SharedRouter {
+        SharedRouter(Arc::new(Router::new()))
+    }
+
+    fn channel_ref(&self, uuid: &Uuid) -> Option<ChannelRef> {
+        let index = self.registry().find(uuid)?;
+        Some(ChannelRef {
+            router: self.clone(),
+            index,
+            generation: self.registry().channels[index]
+                .generation
+                .load(Ordering::Acquire),
+        })
+    }
+
+    /// Join a channel as a sender. Returns `None` if the channel does not exist
+    /// or already has as many senders as it can take.
+    pub fn add_sender(&self, uuid: &Uuid) -> Option<ChannelSender> {
+        let channel = self.channel_ref(uuid)?;
+        let (lane, claim) = channel.ring()?.claim_lane(unix_time())?;
+        Some(ChannelSender {
+            channel,
+            lane,
+            claim,
+        })
+    }
+
+    /// Join a channel as a receiver. Returns `None` if the channel does not exist
+    /// or already has as many receivers as it can take.
+    pub fn subscribe(&self, uuid: &Uuid) -> Option<ChannelReceiver> {
+        let channel = self.channel_ref(uuid)?;
+        let (tap, claim) = channel.ring()?.claim_tap(unix_time())?;
+        Some(ChannelReceiver {
+            channel,
+            tap,
+            claim,
+            next_sequence: [None; MAX_SENDERS],
+        })
+    }
+}
+
+/// A particular incarnation of a channel slot, which stops resolving once the
+/// channel is deleted, even if the slot has since been reused.
+struct ChannelRef {
+    router: SharedRouter,
+    index: usize,
+    generation: u32,
+}
+
+impl ChannelRef {
+    fn get(&self) -> Option<&Channel> {
+        let channel = &self.router.registry().channels[self.index];
+        if channel.is_active() && channel.generation.load(Ordering::Acquire) == self.generation {
+            Some(channel)
         } else {
-            let memory = open_config.open().unwrap();
+            None
+        }
+    }
+
+    fn ring(&self) -> Option<&SampleRing> {
+        self.get().map(|c| &c.ring)
+    }
+}
 
-            unsafe {
-                // Get Pointer to _SharedRouter from shared memory
-                let ptr = *(memory.as_ptr() as *mut *const _SharedRouter);
+/// A sender's place on a channel. Releases its lane when dropped.
+pub struct ChannelSender {
+    channel: ChannelRef,
+    lane: usize,
+    claim: u32,
+}
 
-                Arc::increment_strong_count(ptr);
-                let inner = Arc::from_raw(ptr);
+impl ChannelSender {
+    /// Our channel, as long as our lane on it is still ours
+    fn get(&self) -> Option<&Channel> {
+        self.channel
+            .get()
+            .filter(|c| c.ring.holds_lane(self.lane, self.claim))
+    }
+
+    /// Mix a block of frames laid out as `layout`, running at `sample_rate`, into
+    /// the channel for every receiver subscribed to it. Frames that were mixed for
+    /// a layout the channel no longer carries are dropped.
+    pub fn send(&self, frames: &[Frame], layout: Layout, sample_rate: u32, timing: Timing) {
+        if let Some(channel) = self.get() {
+            if channel.layout() != layout {
+                channel.record_dropped();
+                return;
+            }
 
-                SharedRouter(inner)
+            // NOTE(emily): A channel only has the one rate, so senders running at different
+            // rates on the same channel can't all be right. Whoever sent last wins.
+            channel.sample_rate.store(sample_rate, Ordering::Relaxed);
+            let wide = self.channel.router.registry().wide_lanes(channel);
+            if channel.ring.write(self.lane, frames, timing, wide) {
+                channel
+                    .counters
+                    .transport_jumps
+                    .fetch_add(1, Ordering::Relaxed);
             }
+            channel.counters.blocks_sent.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// What the channel carries, which is what [`ChannelSender::send`] wants
+    pub fn layout(&self) -> Layout {
+        self.channel.get().map(Channel::layout).unwrap_or_default()
+    }
+
+    /// False once the channel has been deleted, or our lane was reclaimed
+    pub fn is_alive(&self) -> bool {
+        self.get().is_some()
+    }
+
+    /// Say that we are still here, which needs doing at least every
+    /// [`INSTANCE_TIMEOUT`] to keep our lane. Returns false if we already lost it.
+    pub fn keep_alive(&self) -> bool {
+        self.channel
+            .ring()
+            .is_some_and(|ring| ring.keep_lane(self.lane, self.claim, unix_time()))
+    }
+}
+
+impl Drop for ChannelSender {
+    fn drop(&mut self) {
+        if let Some(ring) = self.channel.ring() {
+            ring.release_lane(self.lane, self.claim)
+        }
+    }
+}
+
+/// A receiver's place on a channel. Releases its cursor when dropped.
+pub struct ChannelReceiver {
+    channel: ChannelRef,
+    tap: usize,
+    claim: u32,
+    /// Sequence number we expect the next block from each sender lane to have
+    next_sequence: [Option<u64>; MAX_SENDERS],
+}
+
+impl ChannelReceiver {
+    /// Our channel, as long as our tap on it is still ours
+    fn get(&self) -> Option<&Channel> {
+        self.channel
+            .get()
+            .filter(|c| c.ring.holds_tap(self.tap, self.claim))
+    }
+
+    fn ring(&self) -> Option<&SampleRing> {
+        self.get().map(|c| &c.ring)
+    }
+
+    /// Number of samples that can be read right now. Also keeps the channel's
+    /// high water mark up to date.
+    pub fn available(&self) -> usize {
+        let Some(channel) = self.get() else {
+            return 0;
+        };
+
+        let available = channel.ring.available(self.tap);
+        channel
+            .counters
+            .high_water
+            .fetch_max(available as u64, Ordering::Relaxed);
+        available
+    }
+
+    pub fn skip(&self, count: usize) {
+        if let Some(ring) = self.ring() {
+            ring.skip(self.tap, count)
+        }
+    }
+
+    /// Rate the channel is being sent at, if anyone has sent anything yet
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.get().and_then(Channel::sample_rate)
+    }
+
+    /// Where we will read from next
+    pub fn position(&self) -> u64 {
+        self.ring().map(|ring| ring.position(self.tap)).unwrap_or(0)
+    }
+
+    /// How much of what is waiting for us was rendered at or after `at`, on the
+    /// clock senders stamp their blocks with
+    pub fn rendered_since(&self, at: u64) -> usize {
+        self.ring().map(|ring| ring.rendered_since(at)).unwrap_or(0)
+    }
+
+    /// Find where the audio senders made for a song position was, or will be, written
+    pub fn locate(&self, song_position: u64) -> Located {
+        self.ring()
+            .map(|ring| ring.locate(song_position))
+            .unwrap_or(Located::Untimed)
+    }
+
+    /// Read from exactly this position next
+    pub fn seek_to(&self, position: u64) {
+        if let Some(ring) = self.ring() {
+            ring.seek_to(self.tap, position)
+        }
+    }
+
+    /// Put ourselves `latency` samples behind the senders
+    pub fn seek(&self, latency: usize) {
+        if let Some(ring) = self.ring() {
+            ring.seek(self.tap, latency)
+        }
+    }
+
+    /// What the channel carries, which is what [`ChannelReceiver::read`] fills in
+    pub fn layout(&self) -> Layout {
+        self.get().map(Channel::layout).unwrap_or_default()
+    }
+
+    /// Read as many frames as are available into `output`, laid out as `layout`,
+    /// returning how many were read. Anything sent from before the song last
+    /// jumped is stale, and comes back as silence.
+    pub fn read(&mut self, output: &mut [Frame], layout: Layout) -> usize {
+        // NOTE(emily): Not `self.get()`, so that we can still borrow `next_sequence`
+        let (tap, claim) = (self.tap, self.claim);
+        let Some(channel) = self.channel.get().filter(|c| c.ring.holds_tap(tap, claim)) else {
+            return 0;
+        };
+
+        let from = channel.ring.position(self.tap);
+        let wide = self.channel.router.registry().wide_lanes(channel);
+        let count = channel.ring.read(self.tap, output, layout.width(), wide);
+
+        let stale = channel.ring.last_jump().saturating_sub(from) as usize;
+        for f in output[..count.min(stale)].iter_mut() {
+            *f = [0.0; MAX_WIDTH];
+        }
+
+        // Catch blocks that we skipped over or have already seen
+        let (mut missed, mut repeated) = (0, 0);
+        let next_sequence = &mut self.next_sequence;
+        channel
+            .ring
+            .stamps(from, from + count as u64, |lane, stamp| {
+                match next_sequence[lane] {
+                    // NOTE(emily): A sender that just joined starts again from 0
+                    Some(next) if stamp.sequence > next => missed += stamp.sequence - next,
+                    Some(next) if stamp.sequence < next && stamp.sequence != 0 => repeated += 1,
+                    _ => {}
+                }
+                next_sequence[lane] = Some(stamp.sequence + 1);
+            });
+
+        if missed > 0 {
+            channel
+                .counters
+                .blocks_missed
+                .fetch_add(missed, Ordering::Relaxed);
+        }
+        if repeated > 0 {
+            channel
+                .counters
+                .blocks_repeated
+                .fetch_add(repeated, Ordering::Relaxed);
+        }
+
+        count
+    }
+
+    /// We had to throw away what was waiting for us because we fell too far behind
+    pub fn record_dropped(&self) {
+        if let Some(channel) = self.get() {
+            channel.record_dropped()
+        }
+    }
+
+    /// We needed more than had been sent
+    pub fn record_underrun(&self) {
+        if let Some(channel) = self.get() {
+            channel.record_underrun()
+        }
+    }
+
+    /// False once the channel has been deleted, or our tap was reclaimed
+    pub fn is_alive(&self) -> bool {
+        self.get().is_some()
+    }
+
+    /// Same as [`ChannelSender::keep_alive`], for our tap
+    pub fn keep_alive(&self) -> bool {
+        self.channel
+            .ring()
+            .is_some_and(|ring| ring.keep_tap(self.tap, self.claim, unix_time()))
+    }
+}
+
+impl Drop for ChannelReceiver {
+    fn drop(&mut self) {
+        if let Some(ring) = self.channel.ring() {
+            ring.release_tap(self.tap, self.claim)
         }
     }
 }
@@ -0,0 +1,728 @@
+use std::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+
+use crate::layout::{Frame, MAX_WIDTH};
+
+/// How many frames of history each sender lane keeps. Must be a power of two.
+pub const RING_CAPACITY: usize = 8192;
+
+/// How many blocks each sender lane remembers the [`Stamp`] of. Covers
+/// everything a lane holds as long as blocks are at least 16 samples long.
+const STAMP_CAPACITY: usize = RING_CAPACITY / 16;
+
+/// Maximum number of senders that can mix into a single ring
+pub const MAX_SENDERS: usize = 8;
+
+/// How many lanes of each frame a ring holds itself. Anything wider goes in [`WideLanes`].
+pub const LANE_WIDTH: usize = 2;
+
+/// Maximum number of receivers that can read from a single ring
+pub const MAX_RECEIVERS: usize = 16;
+
+/// How far a sender may fall behind the furthest sender on a ring before it
+/// is considered stalled and no longer holds the rest of the ring back.
+const MAX_SENDER_LAG: u64 = RING_CAPACITY as u64 / 4;
+
+const FREE: u32 = 0;
+const CLAIMED: u32 = 1;
+const ACTIVE: u32 = 2;
+
+/// Stored in place of a song position when the host's transport isn't running
+const NO_SONG_POSITION: u64 = u64::MAX;
+
+/// Where a block sits in time, as far as the sender that wrote it knows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timing {
+    /// Samples the sender had rendered before this block
+    pub sample_counter: u64,
+    /// Where the host's song was at the start of the block, if it was playing
+    pub song_position: Option<u64>,
+    /// When the block was rendered, in microseconds on a clock every sender and
+    /// receiver shares
+    pub rendered_at: u64,
+}
+
+/// Everything a sender wrote down about a block alongside its samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stamp {
+    /// Counts up by one for every block a sender writes, from 0 when it joins
+    pub sequence: u64,
+    /// Position in the ring the block starts at
+    pub position: u64,
+    pub len: usize,
+    pub timing: Timing,
+}
+
+/// Where a song position was sent, as far as a ring knows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Located {
+    /// At this position in the ring
+    At(u64),
+    /// Senders say where their song is, but nothing still here was sent for that position
+    Nowhere,
+    /// No sender is saying where its song is
+    Untimed,
+}
+
+#[repr(C)]
+struct StampSlot {
+    sequence: AtomicU64,
+    position: AtomicU64,
+    len: AtomicU64,
+    sample_counter: AtomicU64,
+    song_position: AtomicU64,
+    rendered_at: AtomicU64,
+}
+
+impl StampSlot {
+    /// The stamp of block `sequence`, or None if it has since been overwritten
+    fn load(&self, sequence: u64) -> Option<Stamp> {
+        if self.sequence.load(Ordering::Acquire) != sequence {
+            return None;
+        }
+
+        let stamp = Stamp {
+            sequence,
+            position: self.position.load(Ordering::Relaxed),
+            len: self.len.load(Ordering::Relaxed) as usize,
+            timing: Timing {
+                sample_counter: self.sample_counter.load(Ordering::Relaxed),
+                song_position: match self.song_position.load(Ordering::Relaxed) {
+                    NO_SONG_POSITION => None,
+                    position => Some(position),
+                },
+                rendered_at: self.rendered_at.load(Ordering::Relaxed),
+            },
+        };
+
+        // NOTE(emily): Make sure the sender didn't start on another block in this slot while we read
+        fence(Ordering::Acquire);
+        (self.sequence.load(Ordering::Relaxed) == sequence).then_some(stamp)
+    }
+
+    fn store(&self, stamp: &Stamp) {
+        self.sequence.store(u64::MAX, Ordering::Relaxed);
+        fence(Ordering::Release);
+        self.position.store(stamp.position, Ordering::Relaxed);
+        self.len.store(stamp.len as u64, Ordering::Relaxed);
+        self.sample_counter
+            .store(stamp.timing.sample_counter, Ordering::Relaxed);
+        self.song_position.store(
+            stamp.timing.song_position.unwrap_or(NO_SONG_POSITION),
+            Ordering::Relaxed,
+        );
+        self.rendered_at
+            .store(stamp.timing.rendered_at, Ordering::Relaxed);
+        self.sequence.store(stamp.sequence, Ordering::Release);
+    }
+}
+
+/// Frames written by a single sender. Only the sender that claimed the lane
+/// ever writes to it, so it needs no synchronisation beyond its cursor.
+#[repr(C)]
+struct Lane {
+    state: AtomicU32,
+    /// Bumped every time the lane is claimed, so a sender can tell if it lost it
+    claim: AtomicU32,
+    /// Seconds since the unix epoch when whoever claimed the lane last said they
+    /// were still there
+    alive: AtomicU64,
+    /// First position this sender wrote to, anything before is not ours
+    start: AtomicU64,
+    /// Every position before this has been written
+    cursor: AtomicU64,
+    /// Sequence number the next block written will get
+    sequence: AtomicU64,
+    /// Start of the last block whose song position didn't follow on from
+    /// the one before, or 0 if there hasn't been one
+    last_jump: AtomicU64,
+    stamps: [StampSlot; STAMP_CAPACITY],
+    samples: [[AtomicU32; LANE_WIDTH]; RING_CAPACITY],
+}
+
+/// Room for everything past the first [`LANE_WIDTH`] lanes of every sender in a
+/// ring, for rings that carry more than stereo.
+// NOTE(emily): Kept apart from the ring so that the many channels that are only ever
+// stereo don't pay for room they never use.
+#[repr(C)]
+pub struct WideLanes {
+    samples: [[[AtomicU32; MAX_WIDTH - LANE_WIDTH]; RING_CAPACITY]; MAX_SENDERS],
+}
+
+impl WideLanes {
+    /// Silence everything. Must only be called when no ring is using these.
+    pub fn clear(&self) {
+        for sample in self.samples.iter().flatten().flatten() {
+            sample.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+#[repr(C)]
+struct Tap {
+    state: AtomicU32,
+    /// Same as [`Lane::claim`]
+    claim: AtomicU32,
+    /// Same as [`Lane::alive`]
+    alive: AtomicU64,
+    cursor: AtomicU64,
+}
+
+/// A fixed size, lock-free, multi-producer multi-consumer sample bus.
+///
+/// Each sender writes into its own lane, and receivers sum every lane as they
+/// read, so senders rendering the same block period are mixed together.
+/// Positions only become readable once every (non-stalled) sender has written
+/// past them. Each receiver has its own cursor, so every receiver gets a full
+/// copy of the mixed stream.
+///
+/// Everything in here is plain atomics with a fixed layout, so a ring is valid
+/// when zeroed, can live in memory shared between processes, and nothing in the
+/// read or write path allocates or blocks.
+#[repr(C)]
+pub struct SampleRing {
+    /// Furthest position any sender has written to
+    head: AtomicU64,
+    lanes: [Lane; MAX_SENDERS],
+    taps: [Tap; MAX_RECEIVERS],
+}
+
+fn claim(state: &AtomicU32) -> bool {
+    state
+        .compare_exchange(FREE, CLAIMED, Ordering::Acquire, Ordering::Relaxed)
+        .is_ok()
+}
+
+/// Whether a lane or tap is still held by whoever claimed it as `claim`
+fn is_held(state: &AtomicU32, current: &AtomicU32, claim: u32) -> bool {
+    state.load(Ordering::Acquire) == ACTIVE && current.load(Ordering::Acquire) == claim
+}
+
+/// Free a lane or tap whose owner hasn't said they were still there since `before`
+fn reclaim(state: &AtomicU32, alive: &AtomicU64, before: u64) -> bool {
+    state.load(Ordering::Acquire) == ACTIVE
+        && alive.load(Ordering::Relaxed) < before
+        && state
+            .compare_exchange(ACTIVE, FREE, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+}
+
+impl SampleRing {
+    pub fn new() -> Box<Self> {
+        // SAFETY: SampleRing is entirely atomics, for which all zeroes is valid.
+        // NOTE(emily): This is too big to construct on the stack.
+        let ring = unsafe { Box::<Self>::new_zeroed().assume_init() };
+        ring.reset();
+        ring
+    }
+
+    /// Forget every sender and receiver and start again from nothing. Must only be
+    /// called when nobody else can be using the ring.
+    pub fn reset(&self) {
+        // NOTE(emily): Start a whole ring in, so that there is always history (of silence)
+        // for a new receiver to seek back into, and it can sit at its full latency from the start.
+        self.head.store(RING_CAPACITY as u64, Ordering::Relaxed);
+
+        for lane in self.lanes.iter() {
+            lane.start.store(0, Ordering::Relaxed);
+            lane.cursor.store(0, Ordering::Relaxed);
+            lane.sequence.store(0, Ordering::Relaxed);
+            lane.last_jump.store(0, Ordering::Relaxed);
+            lane.state.store(FREE, Ordering::Release);
+        }
+
+        for tap in self.taps.iter() {
+            tap.cursor.store(0, Ordering::Relaxed);
+            tap.state.store(FREE, Ordering::Release);
+        }
+    }
+
+    /// Whether any sender or receiver is attached
+    pub fn is_in_use(&self) -> bool {
+        self.lanes
+            .iter()
+            .map(|l| &l.state)
+            .chain(self.taps.iter().map(|t| &t.state))
+            .any(|state| state.load(Ordering::Acquire) != FREE)
+    }
+
+    fn slot(position: u64) -> usize {
+        (position & (RING_CAPACITY as u64 - 1)) as usize
+    }
+
+    fn head(&self) -> u64 {
+        self.head.load(Ordering::Acquire)
+    }
+
+    fn is_stalled(head: u64, cursor: u64) -> bool {
+        head.saturating_sub(cursor) > MAX_SENDER_LAG
+    }
+
+    /// Position up to which every active sender has finished writing
+    fn committed(&self) -> u64 {
+        let head = self.head();
+
+        self.lanes
+            .iter()
+            .filter(|l| l.state.load(Ordering::Acquire) == ACTIVE)
+            .map(|l| l.cursor.load(Ordering::Acquire))
+            .filter(|&c| !Self::is_stalled(head, c))
+            .min()
+            .unwrap_or(head)
+    }
+
+    /// Claim a lane to send on, returning it and the claim to hold it by. `now` is
+    /// seconds since the unix epoch.
+    pub fn claim_lane(&self, now: u64) -> Option<(usize, u32)> {
+        let index = self.lanes.iter().position(|l| claim(&l.state))?;
+        let lane = &self.lanes[index];
+        let claim = lane.claim.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+        lane.alive.store(now, Ordering::Relaxed);
+
+        // NOTE(emily): New senders start mixing in at the oldest block that is still
+        // being written, so they line up with whoever is already sending.
+        let position = self.committed();
+        lane.start.store(position, Ordering::Relaxed);
+        lane.cursor.store(position, Ordering::Relaxed);
+        lane.sequence.store(0, Ordering::Relaxed);
+        lane.last_jump.store(0, Ordering::Relaxed);
+        lane.state.store(ACTIVE, Ordering::Release);
+
+        Some((index, claim))
+    }
+
+    /// Whether a lane is still ours, which it stops being if we went quiet for
+    /// long enough that it was reclaimed
+    pub fn holds_lane(&self, index: usize, claim: u32) -> bool {
+        let lane = &self.lanes[index];
+        is_held(&lane.state, &lane.claim, claim)
+    }
+
+    /// Say that we are still using a lane. Returns false if it was reclaimed.
+    pub fn keep_lane(&self, index: usize, claim: u32, now: u64) -> bool {
+        let held = self.holds_lane(index, claim);
+        if held {
+            self.lanes[index].alive.store(now, Ordering::Relaxed);
+        }
+        held
+    }
+
+    pub fn release_lane(&self, index: usize, claim: u32) {
+        if self.holds_lane(index, claim) {
+            self.lanes[index].state.store(FREE, Ordering::Release);
+        }
+    }
+
+    /// Claim a tap to receive from, returning it and the claim to hold it by.
+    /// `now` is seconds since the unix epoch.
+    pub fn claim_tap(&self, now: u64) -> Option<(usize, u32)> {
+        let index = self.taps.iter().position(|t| claim(&t.state))?;
+        let tap = &self.taps[index];
+        let claim = tap.claim.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+        tap.alive.store(now, Ordering::Relaxed);
+
+        // NOTE(emily): New receivers start at the committed position, they are not
+        // interested in whatever was sent before they turned up.
+        tap.cursor.store(self.committed(), Ordering::Relaxed);
+        tap.state.store(ACTIVE, Ordering::Release);
+
+        Some((index, claim))
+    }
+
+    /// Same as [`SampleRing::holds_lane`], for taps
+    pub fn holds_tap(&self, index: usize, claim: u32) -> bool {
+        let tap = &self.taps[index];
+        is_held(&tap.state, &tap.claim, claim)
+    }
+
+    /// Same as [`SampleRing::keep_lane`], for taps
+    pub fn keep_tap(&self, index: usize, claim: u32, now: u64) -> bool {
+        let held = self.holds_tap(index, claim);
+        if held {
+            self.taps[index].alive.store(now, Ordering::Relaxed);
+        }
+        held
+    }
+
+    pub fn release_tap(&self, index: usize, claim: u32) {
+        if self.holds_tap(index, claim) {
+            self.taps[index].state.store(FREE, Ordering::Release);
+        }
+    }
+
+    /// Free every lane and tap whose owner hasn't said they were still there since
+    /// `before`, e.g. because their process crashed. Returns how many were freed.
+    pub fn reclaim(&self, before: u64) -> usize {
+        self.lanes
+            .iter()
+            .map(|l| reclaim(&l.state, &l.alive, before))
+            .chain(
+                self.taps
+                    .iter()
+                    .map(|t| reclaim(&t.state, &t.alive, before)),
+            )
+            .filter(|&freed| freed)
+            .count()
+    }
+
+    /// Write a block of frames into a lane, stamped with when it was rendered,
+    /// with anything past [`LANE_WIDTH`] going into `wide` if there is one.
+    /// Returns true if the song position jumped since the last block.
+    /// Must only be called by whoever claimed the lane.
+    pub fn write(
+        &self,
+        index: usize,
+        frames: &[Frame],
+        timing: Timing,
+        wide: Option<&WideLanes>,
+    ) -> bool {
+        let lane = &self.lanes[index];
+        let head = self.head();
+        let mut cursor = lane.cursor.load(Ordering::Relaxed);
+
+        // If we stalled for long enough that the rest of the ring moved on without us
+        // then pick up again from the front.
+        if Self::is_stalled(head, cursor) {
+            cursor = head;
+            lane.start.store(cursor, Ordering::Relaxed);
+            lane.cursor.store(cursor, Ordering::Release);
+        }
+
+        for (i, f) in frames.iter().enumerate() {
+            let slot = Self::slot(cursor + i as u64);
+            for (stored, s) in lane.samples[slot].iter().zip(f) {
+                stored.store(s.to_bits(), Ordering::Relaxed);
+            }
+
+            // NOTE(emily): Write every lane there is room for, even past what the channel
+            // carries, so nothing stale is left behind there if the channel is made wider.
+            if let Some(wide) = wide {
+                for (stored, s) in wide.samples[index][slot].iter().zip(&f[LANE_WIDTH..]) {
+                    stored.store(s.to_bits(), Ordering::Relaxed);
+                }
+            }
+        }
+
+        let sequence = lane.sequence.load(Ordering::Relaxed);
+        let stamp = Stamp {
+            sequence,
+            position: cursor,
+            len: frames.len(),
+            timing,
+        };
+
+        // A song position that doesn't follow on from the last block means the host seeked
+        let previous = sequence
+            .checked_sub(1)
+            .and_then(|s| Self::stamp_slot(lane, s).load(s));
+        let jumped = match (previous, timing.song_position) {
+            (Some(previous), Some(position)) => previous
+                .timing
+                .song_position
+                .is_some_and(|p| p + previous.len as u64 != position),
+            _ => false,
+        };
+        if jumped {
+            lane.last_jump.store(cursor, Ordering::Relaxed);
+        }
+
+        Self::stamp_slot(lane, sequence).store(&stamp);
+        lane.sequence.store(sequence + 1, Ordering::Relaxed);
+
+        let end = cursor + frames.len() as u64;
+        lane.cursor.store(end, Ordering::Release);
+        self.head.fetch_max(end, Ordering::AcqRel);
+        jumped
+    }
+
+    fn stamp_slot(lane: &Lane, sequence: u64) -> &StampSlot {
+        &lane.stamps[(sequence % STAMP_CAPACITY as u64) as usize]
+    }
+
+    /// Call `f` with the stamp of every block that starts between `from` and `to`,
+    /// along with the lane it is in. Blocks in a lane come oldest first.
+    pub fn stamps(&self, from: u64, to: u64, mut f: impl FnMut(usize, &Stamp)) {
+        for (index, lane) in self.lanes.iter().enumerate() {
+            if lane.state.load(Ordering::Acquire) != ACTIVE {
+                continue;
+            }
+
+            // Walk back to the oldest block we are interested in that we still have
+            let next = lane.sequence.load(Ordering::Acquire);
+            let mut oldest = next;
+            while oldest > 0 && next - oldest < STAMP_CAPACITY as u64 {
+                match Self::stamp_slot(lane, oldest - 1).load(oldest - 1) {
+                    Some(stamp) if stamp.position >= from => oldest -= 1,
+                    _ => break,
+                }
+            }
+
+            for sequence in oldest..next {
+                match Self::stamp_slot(lane, sequence).load(sequence) {
+                    Some(stamp) if stamp.position < to => f(index, &stamp),
+                    _ => break,
+                }
+            }
+        }
+    }
+
+    /// How much of what every sender has finished writing was rendered at or after
+    /// `at`, as far back as the first such block in any lane
+    pub fn rendered_since(&self, at: u64) -> usize {
+        let head = self.head();
+        let committed = self.committed();
+
+        let start = self
+            .lanes
+            .iter()
+            .filter(|l| l.state.load(Ordering::Acquire) == ACTIVE)
+            .filter(|l| !Self::is_stalled(head, l.cursor.load(Ordering::Acquire)))
+            .map(|lane| {
+                // Walk back over every block rendered since then
+                let next = lane.sequence.load(Ordering::Acquire);
+                let mut start = lane.cursor.load(Ordering::Acquire);
+                for sequence in (next.saturating_sub(STAMP_CAPACITY as u64)..next).rev() {
+                    match Self::stamp_slot(lane, sequence).load(sequence) {
+                        Some(stamp) if stamp.timing.rendered_at >= at => start = stamp.position,
+                        _ => break,
+                    }
+                }
+                start
+            })
+            .min()
+            .unwrap_or(committed);
+
+        committed.saturating_sub(start) as usize
+    }
+
+    /// Find where the audio for a song position was, or will be, written. When
+    /// senders disagree about where their songs are, the first one wins.
+    pub fn locate(&self, song_position: u64) -> Located {
+        let oldest = self.oldest();
+        let mut timed = false;
+
+        for lane in self.lanes.iter() {
+            if lane.state.load(Ordering::Acquire) != ACTIVE {
+                continue;
+            }
+
+            // Newest first, so that after a loop we find the latest time round
+            let next = lane.sequence.load(Ordering::Acquire);
+            let mut newest = true;
+            for sequence in (next.saturating_sub(STAMP_CAPACITY as u64)..next).rev() {
+                let Some(stamp) = Self::stamp_slot(lane, sequence).load(sequence) else {
+                    break;
+                };
+                if stamp.position < oldest {
+                    break;
+                }
+                if let Some(start) = stamp.timing.song_position {
+                    timed = true;
+
+                    // NOTE(emily): Past the end of the newest block is fine, the sender
+                    // just hasn't got there yet
+                    let end = start + stamp.len as u64;
+                    if start <= song_position && (newest || song_position < end) {
+                        return Located::At(stamp.position + (song_position - start));
+                    }
+                }
+                newest = false;
+            }
+        }
+
+        if timed {
+            Located::Nowhere
+        } else {
+            Located::Untimed
+        }
+    }
+
+    /// Anything before this position was sent from before the song last jumped
+    pub fn last_jump(&self) -> u64 {
+        self.lanes
+            .iter()
+            .filter(|l| l.state.load(Ordering::Acquire) == ACTIVE)
+            .map(|l| l.last_jump.load(Ordering::Relaxed))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Where this receiver will read from next
+    pub fn position(&self, index: usize) -> u64 {
+        self.taps[index]
+            .cursor
+            .load(Ordering::Relaxed)
+            .max(self.oldest())
+    }
+
+    /// Number of samples this receiver can read right now
+    pub fn available(&self, index: usize) -> usize {
+        let cursor = self.taps[index].cursor.load(Ordering::Relaxed);
+        self.committed()
+            .saturating_sub(cursor.max(self.oldest()))
+            .min(RING_CAPACITY as u64) as usize
+    }
+
+    /// Most samples any receiver has waiting to be read
+    pub fn fill(&self) -> usize {
+        let committed = self.committed();
+        let oldest = self.oldest();
+
+        self.taps
+            .iter()
+            .filter(|t| t.state.load(Ordering::Acquire) == ACTIVE)
+            .map(|t| committed.saturating_sub(t.cursor.load(Ordering::Relaxed).max(oldest)))
+            .max()
+            .unwrap_or(0)
+            .min(RING_CAPACITY as u64) as usize
+    }
+
+    /// Oldest position that still has not been overwritten
+    fn oldest(&self) -> u64 {
+        self.head()
+            .saturating_sub((RING_CAPACITY as u64) - MAX_SENDER_LAG)
+    }
+
+    /// Move this receiver forward without reading anything
+    pub fn skip(&self, index: usize, count: usize) {
+        let tap = &self.taps[index];
+        let cursor = tap.cursor.load(Ordering::Relaxed).max(self.oldest());
+        let count = count.min(self.available(index));
+        tap.cursor.store(cursor + count as u64, Ordering::Relaxed);
+    }
+
+    /// Put this receiver at exactly this position, or as close as we still have history for
+    pub fn seek_to(&self, index: usize, position: u64) {
+        self.taps[index]
+            .cursor
+            .store(position.max(self.oldest()), Ordering::Relaxed);
+    }
+
+    /// Put this receiver exactly `behind` samples behind the committed position,
+    /// or as close as we still have history for
+    pub fn seek(&self, index: usize, behind: usize) {
+        let cursor = self
+            .committed()
+            .saturating_sub(behind as u64)
+            .max(self.oldest());
+        self.taps[index].cursor.store(cursor, Ordering::Relaxed);
+    }
+
+    /// Read the mix of every sender into the first `width` lanes of `output`,
+    /// returning how many frames were read. Lanes past [`LANE_WIDTH`] come from
+    /// `wide`, and are silent without it. Must only be called by whoever claimed
+    /// this receiver.
+    pub fn read(
+        &self,
+        index: usize,
+        output: &mut [Frame],
+        width: usize,
+        wide: Option<&WideLanes>,
+    ) -> usize {
+        let tap = &self.taps[index];
+        let committed = self.committed();
+
+        // If this receiver fell so far behind that the senders have lapped it
+        // then skip it forward to the oldest sample we still have.
+        let cursor = tap.cursor.load(Ordering::Relaxed).max(self.oldest());
+        let count = (committed.saturating_sub(cursor) as usize).min(output.len());
+
+        for s in output[..count].iter_mut() {
+            *s = [0.0; MAX_WIDTH];
+        }
+
+        let wide = wide.filter(|_| width > LANE_WIDTH);
+
+        for (l, lane) in self.lanes.iter().enumerate() {
+            if lane.state.load(Ordering::Acquire) != ACTIVE {
+                continue;
+            }
+
+            let start = lane.start.load(Ordering::Relaxed);
+            let end = lane.cursor.load(Ordering::Acquire);
+
+            for (i, s) in output[..count].iter_mut().enumerate() {
+                let position = cursor + i as u64;
+                if position < start || position >= end {
+                    continue;
+                }
+
+                let slot = Self::slot(position);
+                for (s, stored) in s.iter_mut().zip(&lane.samples[slot]).take(width) {
+                    *s += f32::from_bits(stored.load(Ordering::Relaxed));
+                }
+
+                if let Some(wide) = wide {
+                    let rest = s[LANE_WIDTH..].iter_mut().zip(&wide.samples[l][slot]);
+                    for (s, stored) in rest.take(width - LANE_WIDTH) {
+                        *s += f32::from_bits(stored.load(Ordering::Relaxed));
+                    }
+                }
+            }
+        }
+
+        tap.cursor.store(cursor + count as u64, Ordering::Relaxed);
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quiet_owners_lose_their_place() {
+        let ring = SampleRing::new();
+        let (lane, claim) = ring.claim_lane(100).unwrap();
+        let (tap, tap_claim) = ring.claim_tap(100).unwrap();
+
+        // Still here
+        assert!(ring.keep_lane(lane, claim, 120));
+        assert_eq!(ring.reclaim(110), 1);
+        assert!(ring.holds_lane(lane, claim));
+        assert!(!ring.holds_tap(tap, tap_claim));
+        assert!(ring.is_in_use());
+
+        assert_eq!(ring.reclaim(130), 1);
+        assert!(!ring.is_in_use());
+        assert!(!ring.keep_lane(lane, claim, 130));
+    }
+
+    #[test]
+    fn lost_lanes_stay_with_their_new_owner() {
+        let ring = SampleRing::new();
+        let (lane, old) = ring.claim_lane(100).unwrap();
+        ring.reclaim(200);
+
+        let (again, new) = ring.claim_lane(200).unwrap();
+        assert_eq!(again, lane);
+        assert!(!ring.holds_lane(lane, old));
+
+        // Whoever had it before letting go doesn't take it from whoever has it now
+        ring.release_lane(lane, old);
+        assert!(ring.holds_lane(lane, new));
+    }
+
+    #[test]
+    fn blocks_are_told_apart_by_when_they_were_rendered() {
+        let ring = SampleRing::new();
+        let (first, _) = ring.claim_lane(100).unwrap();
+        let (second, _) = ring.claim_lane(100).unwrap();
+        let frames = [[0.0; MAX_WIDTH]; 64];
+        let at = |rendered_at| Timing {
+            rendered_at,
+            ..Timing::default()
+        };
+
+        ring.write(first, &frames, at(100), None);
+        ring.write(second, &frames, at(100), None);
+        ring.write(first, &frames, at(200), None);
+        assert_eq!(ring.rendered_since(150), 0);
+        assert_eq!(ring.rendered_since(50), 64);
+
+        // Only counts once every sender has written it
+        ring.write(second, &frames, at(210), None);
+        assert_eq!(ring.rendered_since(150), 64);
+        assert_eq!(ring.rendered_since(50), 128);
+        assert_eq!(ring.rendered_since(300), 0);
+    }
+}
@@ -0,0 +1,392 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    dsp::underrun::UnderrunBehaviour,
+    layout::{Layout, Port},
+    params::Params,
+    router::{ChannelInfo, ChannelSnapshot, GraphChannel},
+    AlignmentMode, Mode, DEFAULT_LATENCY,
+};
+
+/// Everything about an instance that is saved with a project
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstanceState {
+    /// Who we were to other instances, if we are picking up where we left off
+    pub instance: Option<Uuid>,
+    pub mode: Mode,
+    pub channel: Option<ChannelSnapshot>,
+    /// Every channel our project was using when we were saved, and who was on
+    /// them, so that the whole graph comes back no matter which instance loads first
+    pub graph: Vec<GraphChannel>,
+    pub params: Params,
+    pub latency: usize,
+    pub underrun_behaviour: UnderrunBehaviour,
+    /// What other instances see us as
+    pub label: String,
+    /// Where our stereo sits on our channel
+    pub port: Port,
+    pub alignment: AlignmentMode,
+}
+
+impl Default for InstanceState {
+    fn default() -> Self {
+        Self {
+            instance: None,
+            mode: Mode::Receiver,
+            channel: None,
+            graph: vec![],
+            params: Params::default(),
+            latency: DEFAULT_LATENCY,
+            underrun_behaviour: UnderrunBehaviour::Mute,
+            label: String::new(),
+            port: Port::Mix,
+            alignment: AlignmentMode::Queue,
+        }
+    }
+}
+
+/// What actually gets written to a project. Old versions must never change
+/// shape, only ever add new ones.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub enum SaveState {
+    Ver1 {
+        mode: Mode,
+        uuid: Uuid,
+    },
+    /// Written whether or not we have a channel
+    Ver2(Ver2State),
+}
+
+/// Exactly what [`SaveState::Ver2`] saves. Unlike [`InstanceState`] this must
+/// never change, since saved state doesn't say which fields it has.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Ver2State {
+    instance: Option<Uuid>,
+    mode: Mode,
+    channel: Option<ChannelSnapshot>,
+    graph: Vec<GraphChannel>,
+    params: Params,
+    latency: usize,
+    underrun_behaviour: UnderrunBehaviour,
+    label: String,
+    port: Port,
+    alignment: AlignmentMode,
+}
+
+impl SaveState {
+    pub fn new(state: InstanceState) -> Self {
+        SaveState::Ver2(Ver2State {
+            instance: state.instance,
+            mode: state.mode,
+            channel: state.channel,
+            graph: state.graph,
+            params: state.params,
+            latency: state.latency,
+            underrun_behaviour: state.underrun_behaviour,
+            label: state.label,
+            port: state.port,
+            alignment: state.alignment,
+        })
+    }
+
+    /// Bring state saved by any version up to date, filling in anything it
+    /// didn't save with defaults
+    pub fn migrate(self) -> InstanceState {
+        match self {
+            // NOTE(emily): Channels saved before we kept track of when they changed
+            // lose to any channel that already exists
+            SaveState::Ver1 { mode, uuid } => InstanceState {
+                mode,
+                channel: Some(ChannelSnapshot {
+                    info: ChannelInfo {
+                        id: uuid,
+                        name: String::new(),
+                        colour: None,
+                        layout: Layout::Stereo,
+                    },
+                    modified: 0,
+                }),
+                ..Default::default()
+            },
+            SaveState::Ver2(state) => InstanceState {
+                instance: state.instance,
+                mode: state.mode,
+                channel: state.channel,
+                graph: state.graph,
+                params: state.params,
+                latency: state.latency,
+                underrun_behaviour: state.underrun_behaviour,
+                label: state.label,
+                port: state.port,
+                alignment: state.alignment,
+            },
+        }
+    }
+}
+
+/// The parts of an instance worth sharing between projects, saved as JSON so
+/// that people can read and edit them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Preset {
+    pub mode: Mode,
+    /// Channel to join, found by name when the preset is loaded
+    pub channel_name: Option<String>,
+    pub params: Params,
+    pub latency: usize,
+    pub underrun_behaviour: UnderrunBehaviour,
+    #[serde(default)]
+    pub port: Port,
+    /// Layout to give the channel, if loading the preset makes it
+    #[serde(default)]
+    pub channel_layout: Layout,
+    #[serde(default)]
+    pub alignment: AlignmentMode,
+}
+
+impl Preset {
+    pub fn new(state: &InstanceState) -> Self {
+        Self {
+            mode: state.mode,
+            channel_name: state
+                .channel
+                .as_ref()
+                .map(|c| c.info.name.clone())
+                .filter(|name| !name.is_empty()),
+            params: state.params,
+            latency: state.latency,
+            underrun_behaviour: state.underrun_behaviour,
+            port: state.port,
+            channel_layout: state
+                .channel
+                .as_ref()
+                .map(|c| c.info.layout)
+                .unwrap_or_default(),
+            alignment: state.alignment,
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    /// Where presets go when they are given by name rather than a full path
+    pub fn dir() -> PathBuf {
+        std::env::var_os("APPDATA")
+            .or_else(|| std::env::var_os("HOME"))
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir)
+            .join("emilydotgg-feedback")
+            .join("presets")
+    }
+
+    /// Work out which file the user means by `path`, resolving anything
+    /// relative against [`Preset::dir`]
+    pub fn file(path: &Path) -> eyre::Result<PathBuf> {
+        if path.as_os_str().is_empty() {
+            eyre::bail!("no preset file given");
+        }
+
+        let mut file = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            Self::dir().join(path)
+        };
+
+        if file.extension().is_none() {
+            file.set_extension("json");
+        }
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::Param;
+
+    fn round_trip(state: &SaveState) -> SaveState {
+        bincode::deserialize(&bincode::serialize(state).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn ver1_migrates() {
+        let uuid = Uuid::new_v4();
+        let state = round_trip(&SaveState::Ver1 {
+            mode: Mode::Sender,
+            uuid,
+        })
+        .migrate();
+
+        assert_eq!(state.mode, Mode::Sender);
+        let channel = state.channel.unwrap();
+        assert_eq!(channel.info.id, uuid);
+        assert_eq!(channel.info.layout, Layout::Stereo);
+        assert_eq!(channel.modified, 0);
+        assert_eq!(state.params, Params::default());
+        assert_eq!(state.latency, DEFAULT_LATENCY);
+    }
+
+    fn params() -> Params {
+        let mut params = Params::default();
+        params.set(Param::Mix, 0.25);
+        params.set(Param::Invert, 1.0);
+        params
+    }
+
+    fn snapshot(name: &str, modified: u64, layout: Layout) -> ChannelSnapshot {
+        ChannelSnapshot {
+            info: ChannelInfo {
+                id: Uuid::new_v4(),
+                name: name.into(),
+                colour: None,
+                layout,
+            },
+            modified,
+        }
+    }
+
+    #[test]
+    fn ver2_round_trips() {
+        let state = InstanceState {
+            instance: Some(Uuid::new_v4()),
+            mode: Mode::Return,
+            channel: Some(snapshot("bus", 10, Layout::Surround51)),
+            graph: vec![
+                GraphChannel {
+                    channel: snapshot("bus", 10, Layout::Surround51),
+                    members: vec![(Uuid::new_v4(), Mode::Return)],
+                },
+                GraphChannel {
+                    channel: snapshot("other", 20, Layout::Discrete(8)),
+                    members: vec![
+                        (Uuid::new_v4(), Mode::Sender),
+                        (Uuid::new_v4(), Mode::Receiver),
+                    ],
+                },
+            ],
+            params: params(),
+            latency: 512,
+            underrun_behaviour: UnderrunBehaviour::Mute,
+            label: "Insert 3".into(),
+            port: Port::Pair(2),
+            alignment: AlignmentMode::Transport,
+        };
+
+        assert_eq!(round_trip(&SaveState::new(state.clone())).migrate(), state);
+    }
+
+    #[test]
+    fn preset_files_are_resolved() {
+        assert!(Preset::file(Path::new("")).is_err());
+        assert_eq!(
+            Preset::file(Path::new("drums")).unwrap(),
+            Preset::dir().join("drums.json")
+        );
+
+        let absolute = std::env::temp_dir().join("bus.preset");
+        assert_eq!(Preset::file(&absolute).unwrap(), absolute);
+    }
+
+    #[test]
+    fn preset_round_trips() {
+        let preset = Preset {
+            mode: Mode::Sender,
+            channel_name: Some("drums".into()),
+            params: params(),
+            latency: 256,
+            underrun_behaviour: UnderrunBehaviour::Hold,
+            port: Port::Pair(1),
+            channel_layout: Layout::Quad,
+            alignment: AlignmentMode::Transport,
+        };
+
+        let json = preset.to_json().unwrap();
+        assert!(json.contains("\"Mix\": 0.25"));
+        assert_eq!(Preset::from_json(&json).unwrap(), preset);
+    }
+
+    #[test]
+    fn preset_fills_in_missing_params() {
+        let json = r#"{
+            "mode": "Return",
+            "channel_name": null,
+            "params": { "Mute": 1.0 },
+            "latency": 1024,
+            "underrun_behaviour": "Mute"
+        }"#;
+
+        let preset = Preset::from_json(json).unwrap();
+        assert_eq!(preset.port, Port::Mix);
+        assert_eq!(preset.channel_layout, Layout::Stereo);
+        assert_eq!(preset.alignment, AlignmentMode::Queue);
+        let mut params = Params::default();
+        params.set(Param::Mute, 1.0);
+        assert_eq!(preset.params, params);
+    }
+
+    #[test]
+    fn ver1_blob_still_reads() {
+        // NOTE(emily): Exactly what the first release wrote, make sure old projects keep loading
+        let uuid = Uuid::new_v4();
+        let mut blob = vec![0, 0, 0, 0, 1, 0, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0];
+        blob.extend_from_slice(uuid.as_bytes());
+
+        let state = bincode::deserialize::<SaveState>(&blob).unwrap().migrate();
+        assert_eq!(state.mode, Mode::Sender);
+        assert_eq!(state.channel.unwrap().info.id, uuid);
+    }
+
+    #[test]
+    fn ver2_blob_still_reads() {
+        // NOTE(emily): Exactly what Ver2 writes, make sure projects saved with it keep loading
+        #[rustfmt::skip]
+        let blob = [
+            1, 0, 0, 0,
+            // instance
+            1, 16, 0, 0, 0, 0, 0, 0, 0, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+            // mode, channel and graph
+            3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            // params
+            8, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 85, 85, 85, 63,
+            1, 0, 0, 0, 85, 85, 85, 63,
+            2, 0, 0, 0, 0, 0, 128, 62,
+            3, 0, 0, 0, 0, 0, 0, 0,
+            4, 0, 0, 0, 0, 0, 128, 63,
+            5, 0, 0, 0, 205, 204, 124, 63,
+            6, 0, 0, 0, 0, 0, 0, 0,
+            7, 0, 0, 0, 0, 0, 0, 0,
+            // latency, underrun behaviour, label, port and alignment
+            0, 2, 0, 0, 0, 0, 0, 0,
+            1, 0, 0, 0,
+            1, 0, 0, 0, 0, 0, 0, 0, 65,
+            1, 0, 0, 0, 1,
+            1, 0, 0, 0,
+        ];
+
+        let state = bincode::deserialize::<SaveState>(&blob).unwrap().migrate();
+        assert_eq!(state.params.get(Param::Mix), 0.25);
+        assert!(state.params.is_on(Param::Invert));
+        assert_eq!(
+            state,
+            InstanceState {
+                instance: Some(Uuid::from_bytes([7; 16])),
+                mode: Mode::Return,
+                params: state.params,
+                latency: 512,
+                underrun_behaviour: UnderrunBehaviour::Hold,
+                label: "A".into(),
+                port: Port::Pair(1),
+                alignment: AlignmentMode::Transport,
+                ..Default::default()
+            }
+        );
+    }
+}
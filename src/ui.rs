@@ -1,33 +1,74 @@
-use std::{sync::Arc, thread::JoinHandle};
+use std::{sync::Arc, thread::JoinHandle, time::Duration};
 
 use eyre::Result;
 
 use futures::stream;
-use iced::{Alignment, Application, Padding};
+use iced::{widget::canvas, Alignment, Application, Padding};
 use parking_lot::Mutex;
 use tokio::sync::mpsc;
 use uuid::Uuid;
 
-use crate::{Mode, PluginStateChange};
-
-pub mod window_handle;
+use crate::{
+    dsp::{
+        meter::{Meters, Reading},
+        underrun::UnderrunBehaviour,
+    },
+    layout::{Layout, Port},
+    message::{PluginMessage, PluginStateChange},
+    params::{DelayUnit, Param, Params},
+    router::{ChannelInfo, ChannelStats, Colour, InstanceInfo},
+    window_handle::WindowHandle,
+    AlignmentMode, Mode, LATENCIES,
+};
+
+pub mod platform;
 
 /// Message sent from Plugin to UI
 #[derive(Debug, Clone)]
 pub enum UIMessage {
-    ShowEditor(window_handle::WindowHandle),
+    ShowEditor(WindowHandle),
+    /// Where to read our levels from
+    Meters(Arc<Meters>),
     StateChange(PluginStateChange),
     Die,
 }
 
-/// Message sent from UI to Plugin
-#[derive(Debug)]
-pub enum PluginMessage {
-    SetEditor(window_handle::WindowHandle),
-    SetMode(Mode),
-    NewChannel,
-    SelectChannel(Uuid),
-    AskChannels,
+/// Colours on offer for tagging channels with
+const PALETTE: [Colour; 6] = [
+    [0xE0, 0x5A, 0x5A],
+    [0xE0, 0xA0, 0x48],
+    [0xD8, 0xD0, 0x50],
+    [0x5C, 0xC0, 0x6C],
+    [0x50, 0x98, 0xE0],
+    [0xB0, 0x70, 0xE0],
+];
+
+/// How often the meters and scope are redrawn
+const METER_INTERVAL: Duration = Duration::from_millis(33);
+
+/// Width of each channel's column in the routing matrix
+const MATRIX_CELL_WIDTH: f32 = 28.0;
+
+/// Quietest level the meters show, in dB
+const METER_FLOOR_DB: f32 = -60.0;
+
+/// Somewhere to send messages to the UI from, that can be handed out separately
+/// from the [`UIHandle`] itself
+#[derive(Clone)]
+pub struct UISender(mpsc::Sender<UIMessage>);
+
+impl UISender {
+    pub fn send_sync(&self, message: UIMessage) -> Result<()> {
+        self.0.blocking_send(message)?;
+        Ok(())
+    }
+
+    /// Send without waiting, for when we cannot afford to block.
+    /// Fails if the UI has fallen behind.
+    pub fn try_send(&self, message: UIMessage) -> Result<()> {
+        self.0.try_send(message)?;
+        Ok(())
+    }
 }
 
 pub struct UIHandle {
@@ -55,7 +96,7 @@ impl UIHandle {
             settings.antialiasing = true;
             settings.window.resizable = false;
             settings.window.visible = false;
-            settings.window.size = (200, 200);
+            settings.window.size = (300, 900);
             settings.window.decorations = false;
             UI::run(settings).unwrap();
         });
@@ -69,10 +110,14 @@ impl UIHandle {
         Ok(())
     }
 
+    pub fn sender(&self) -> UISender {
+        UISender(self.tx.clone())
+    }
+
     pub fn join(&self) {
-        self.thread_handle.lock().take().map(|h| {
+        if let Some(h) = self.thread_handle.lock().take() {
             h.join().unwrap();
-        });
+        }
     }
 }
 
@@ -84,10 +129,37 @@ struct UIFlags {
 struct UI {
     rx: Arc<tokio::sync::Mutex<mpsc::Receiver<UIMessage>>>,
     tx: mpsc::Sender<PluginMessage>,
-    hwnd: Mutex<window_handle::WindowHandle>,
+    hwnd: Mutex<WindowHandle>,
     selected_channel: Option<Uuid>,
     selected_mode: Option<Mode>,
-    available_channels: Vec<Uuid>,
+    available_channels: Vec<ChannelInfo>,
+    /// Name of the selected channel as it is being edited
+    channel_name: String,
+    /// Our label as it is being edited
+    label: String,
+    port: Port,
+    /// Which of `instances` is us
+    ours: Option<Uuid>,
+    instances: Vec<InstanceInfo>,
+    latency: Option<usize>,
+    reported_latency: usize,
+    sample_rate: u32,
+    /// Host tempo in beats per minute
+    tempo: f32,
+    underrun_behaviour: Option<UnderrunBehaviour>,
+    alignment: Option<AlignmentMode>,
+    underruns: usize,
+    stats: Option<ChannelStats>,
+    drift_ppm: f64,
+    meters: Option<Arc<Meters>>,
+    input_levels: Reading,
+    channel_levels: Reading,
+    scope: Vec<f32>,
+    params: Params,
+    /// Whether the safety limiter has recently had to step in
+    protection: bool,
+    preset_path: String,
+    preset_status: String,
 }
 
 #[derive(Debug, Clone)]
@@ -95,8 +167,27 @@ enum Message {
     /// A message from the Plugin to the UI
     PluginMessage(UIMessage),
     ModeSelected(Mode),
-    ChannelSelected(Uuid),
+    ChannelSelected(ChannelInfo),
+    ChannelNameChanged(String),
+    ChannelNameSubmitted,
+    ChannelColourSelected(Option<Colour>),
+    LayoutSelected(Layout),
+    PortSelected(Port),
+    LabelChanged(String),
+    LabelSubmitted,
+    /// A cell in the routing matrix was clicked
+    Connect(Uuid, Option<Uuid>),
     NewChannel,
+    DeleteChannel,
+    LatencySelected(usize),
+    UnderrunBehaviourSelected(UnderrunBehaviour),
+    AlignmentSelected(AlignmentMode),
+    ParamChanged(Param, f32),
+    PresetPathChanged(String),
+    ExportPreset,
+    ImportPreset,
+    /// Time to read the meters again
+    Tick,
     None,
 }
 
@@ -111,10 +202,32 @@ impl iced::Application for UI {
             Self {
                 tx: flags.tx.clone(),
                 rx: Arc::new(tokio::sync::Mutex::new(flags.rx)),
-                hwnd: Mutex::new(window_handle::WindowHandle::null()),
+                hwnd: Mutex::new(WindowHandle::null()),
                 selected_channel: None,
                 selected_mode: Some(Mode::Receiver),
                 available_channels: vec![],
+                channel_name: String::new(),
+                label: String::new(),
+                port: Port::Mix,
+                ours: None,
+                instances: vec![],
+                latency: None,
+                reported_latency: 0,
+                sample_rate: 44100,
+                tempo: 120.0,
+                underrun_behaviour: None,
+                alignment: None,
+                underruns: 0,
+                stats: None,
+                drift_ppm: 0.0,
+                meters: None,
+                input_levels: Reading::default(),
+                channel_levels: Reading::default(),
+                scope: vec![],
+                params: Params::default(),
+                protection: false,
+                preset_path: String::new(),
+                preset_status: String::new(),
             },
             iced::Command::batch([iced::Command::perform(
                 async move {
@@ -143,19 +256,7 @@ impl iced::Application for UI {
                         iced::window::Mode::Hidden
                     });
 
-                unsafe {
-                    use windows::Win32::UI::WindowsAndMessaging;
-                    let self_hwnd = self.hwnd.lock().as_hwnd();
-
-                    let show_cmd = if handle.is_valid() {
-                        WindowsAndMessaging::SW_SHOW
-                    } else {
-                        WindowsAndMessaging::SW_HIDE
-                    };
-
-                    WindowsAndMessaging::SetParent(self_hwnd, handle.as_hwnd());
-                    WindowsAndMessaging::ShowWindow(self_hwnd, show_cmd);
-                }
+                platform::embed(*self.hwnd.lock(), handle);
 
                 // NOTE(emily): Send our (iced's) hwnd to FL to set as the editor window
                 let message = PluginMessage::SetEditor(self.hwnd.lock().clone());
@@ -171,17 +272,69 @@ impl iced::Application for UI {
                     change_mode_command,
                 ]))
             }
+            Message::PluginMessage(UIMessage::Meters(meters)) => {
+                self.meters = Some(meters);
+                None
+            }
             Message::PluginMessage(UIMessage::StateChange(state_change)) => {
                 match state_change {
                     PluginStateChange::AvailableChannels(channels) => {
                         self.available_channels = channels;
+                        self.update_channel_name();
                     }
                     PluginStateChange::ChannelId(id) => {
                         self.selected_channel = Some(id);
+                        self.update_channel_name();
                     }
                     PluginStateChange::Mode(mode) => {
                         self.selected_mode = Some(mode);
                     }
+                    PluginStateChange::Label(label) => {
+                        self.label = label;
+                    }
+                    PluginStateChange::Port(port) => {
+                        self.port = port;
+                    }
+                    PluginStateChange::Instances { ours, instances } => {
+                        self.ours = Some(ours);
+                        self.instances = instances;
+                    }
+                    PluginStateChange::Latency {
+                        setting,
+                        reported,
+                        sample_rate,
+                    } => {
+                        self.latency = Some(setting);
+                        self.reported_latency = reported;
+                        self.sample_rate = sample_rate;
+                    }
+                    PluginStateChange::UnderrunBehaviour(behaviour) => {
+                        self.underrun_behaviour = Some(behaviour);
+                    }
+                    PluginStateChange::Alignment(alignment) => {
+                        self.alignment = Some(alignment);
+                    }
+                    PluginStateChange::Underruns(underruns) => {
+                        self.underruns = underruns;
+                    }
+                    PluginStateChange::ChannelStats(stats) => {
+                        self.stats = stats;
+                    }
+                    PluginStateChange::Drift(ppm) => {
+                        self.drift_ppm = ppm;
+                    }
+                    PluginStateChange::Param(param, value) => {
+                        self.params.set(param, value);
+                    }
+                    PluginStateChange::Tempo(tempo) => {
+                        self.tempo = tempo;
+                    }
+                    PluginStateChange::Protection(engaged) => {
+                        self.protection = engaged;
+                    }
+                    PluginStateChange::PresetStatus(status) => {
+                        self.preset_status = status;
+                    }
                 };
                 None
             }
@@ -202,7 +355,9 @@ impl iced::Application for UI {
                 ))
             }
             Message::ChannelSelected(channel) => {
+                let channel = channel.id;
                 self.selected_channel = Some(channel);
+                self.update_channel_name();
 
                 let host_message_tx = self.tx.clone();
                 Some(iced::Command::perform(
@@ -215,6 +370,73 @@ impl iced::Application for UI {
                     |_| Message::None,
                 ))
             }
+            Message::ChannelNameChanged(name) => {
+                self.channel_name = name;
+                None
+            }
+            Message::ChannelNameSubmitted => self.selected_channel.map(|channel| {
+                self.send_to_plugin(PluginMessage::RenameChannel(
+                    channel,
+                    self.channel_name.clone(),
+                ))
+            }),
+            Message::ChannelColourSelected(colour) => self.selected_channel.map(|channel| {
+                self.send_to_plugin(PluginMessage::SetChannelColour(channel, colour))
+            }),
+            Message::LayoutSelected(layout) => self.selected_channel.map(|channel| {
+                self.send_to_plugin(PluginMessage::SetChannelLayout(channel, layout))
+            }),
+            Message::PortSelected(port) => {
+                self.port = port;
+                Some(self.send_to_plugin(PluginMessage::SetPort(port)))
+            }
+            Message::LabelChanged(label) => {
+                self.label = label;
+                None
+            }
+            Message::LabelSubmitted => {
+                Some(self.send_to_plugin(PluginMessage::SetLabel(self.label.clone())))
+            }
+            Message::Connect(instance, channel) => {
+                Some(self.send_to_plugin(PluginMessage::Connect(instance, channel)))
+            }
+            Message::LatencySelected(latency) => {
+                self.latency = Some(latency);
+                Some(self.send_to_plugin(PluginMessage::SetLatency(latency)))
+            }
+            Message::UnderrunBehaviourSelected(behaviour) => {
+                self.underrun_behaviour = Some(behaviour);
+                Some(self.send_to_plugin(PluginMessage::SetUnderrunBehaviour(behaviour)))
+            }
+            Message::AlignmentSelected(alignment) => {
+                self.alignment = Some(alignment);
+                Some(self.send_to_plugin(PluginMessage::SetAlignment(alignment)))
+            }
+            Message::ParamChanged(param, value) => {
+                self.params.set(param, value);
+                Some(self.send_to_plugin(PluginMessage::SetParam(param, value)))
+            }
+            Message::PresetPathChanged(path) => {
+                self.preset_path = path;
+                None
+            }
+            Message::ExportPreset => Some(
+                self.send_to_plugin(PluginMessage::ExportPreset(self.preset_path.trim().into())),
+            ),
+            Message::ImportPreset => Some(
+                self.send_to_plugin(PluginMessage::ImportPreset(self.preset_path.trim().into())),
+            ),
+            Message::Tick => {
+                if let Some(meters) = self.meters.as_ref() {
+                    self.input_levels = meters.input.read();
+                    self.channel_levels = meters.channel.read();
+                    meters.scope.read(&mut self.scope);
+                }
+                None
+            }
+            Message::DeleteChannel => self
+                .selected_channel
+                .map(|channel| self.send_to_plugin(PluginMessage::DeleteChannel(channel))),
             Message::NewChannel => {
                 let host_message_tx = self.tx.clone();
                 Some(iced::Command::perform(
@@ -235,28 +457,139 @@ impl iced::Application for UI {
     }
 
     fn subscription(&self) -> iced::Subscription<Self::Message> {
-        iced_native::subscription::Subscription::batch([iced_native::Subscription::from_recipe(
-            UIMessageWatcher {
+        iced_native::subscription::Subscription::batch([
+            iced_native::Subscription::from_recipe(UIMessageWatcher {
                 rx: self.rx.clone(),
-            },
-        )])
+            }),
+            iced::time::every(METER_INTERVAL).map(|_| Message::Tick),
+        ])
     }
 
     fn view(&self) -> iced::Element<'_, Self::Message, iced::Renderer<Self::Theme>> {
-        iced::widget::column!(
+        let selected_channel = self.selected_channel_info();
+        let layout = selected_channel.as_ref().map(|c| c.layout);
+
+        let swatches: Vec<iced::Element<'_, Self::Message, iced::Renderer<Self::Theme>>> = PALETTE
+            .iter()
+            .map(|&colour| {
+                iced::widget::button(iced::widget::text("■").style(to_color(colour)))
+                    .on_press(Message::ChannelColourSelected(Some(colour)))
+                    .into()
+            })
+            .chain(std::iter::once(
+                iced::widget::button("×")
+                    .on_press(Message::ChannelColourSelected(None))
+                    .into(),
+            ))
+            .collect();
+
+        let content = iced::widget::column!(
             iced::widget::text("emilydotgg-feedback"),
+            iced::widget::text_input("Instance label", &self.label, Message::LabelChanged)
+                .on_submit(Message::LabelSubmitted),
             iced::widget::pick_list(&Mode::ALL[..], self.selected_mode, Message::ModeSelected),
             iced::widget::pick_list(
                 &self.available_channels,
-                self.selected_channel,
+                selected_channel.clone(),
                 Message::ChannelSelected
             ),
-            iced::widget::button("New channel").on_press(Message::NewChannel),
+            iced::widget::row!(
+                iced::widget::text("■").style(
+                    selected_channel
+                        .and_then(|c| c.colour)
+                        .map(to_color)
+                        .unwrap_or(iced::Color::from_rgb(0.5, 0.5, 0.5))
+                ),
+                iced::widget::text_input(
+                    "Channel name",
+                    &self.channel_name,
+                    Message::ChannelNameChanged
+                )
+                .on_submit(Message::ChannelNameSubmitted),
+            )
+            .align_items(Alignment::Center)
+            .spacing(8),
+            iced::widget::row(swatches).spacing(4),
+            iced::widget::row!(
+                iced::widget::button("New channel").on_press(Message::NewChannel),
+                iced::widget::button("Delete channel").on_press(Message::DeleteChannel),
+            )
+            .spacing(8),
+            iced::widget::row!(
+                iced::widget::text("Layout"),
+                iced::widget::pick_list(&Layout::ALL[..], layout, Message::LayoutSelected),
+                iced::widget::text("Port"),
+                iced::widget::pick_list(
+                    Port::all(layout.unwrap_or_default()),
+                    Some(self.port),
+                    Message::PortSelected
+                ),
+            )
+            .align_items(Alignment::Center)
+            .spacing(8),
+            self.matrix_view(),
+            iced::widget::row!(
+                iced::widget::text("Latency"),
+                iced::widget::pick_list(&LATENCIES[..], self.latency, Message::LatencySelected),
+            )
+            .align_items(Alignment::Center)
+            .spacing(8),
+            iced::widget::text(format!(
+                "{} samples ({:.1} ms)",
+                self.reported_latency,
+                self.reported_latency as f32 * 1000.0 / self.sample_rate.max(1) as f32
+            )),
+            iced::widget::row!(
+                iced::widget::text("On underrun"),
+                iced::widget::pick_list(
+                    &UnderrunBehaviour::ALL[..],
+                    self.underrun_behaviour,
+                    Message::UnderrunBehaviourSelected
+                ),
+            )
+            .align_items(Alignment::Center)
+            .spacing(8),
+            iced::widget::row!(
+                iced::widget::text("Align by"),
+                iced::widget::pick_list(
+                    &AlignmentMode::ALL[..],
+                    self.alignment,
+                    Message::AlignmentSelected
+                ),
+            )
+            .align_items(Alignment::Center)
+            .spacing(8),
+            iced::widget::text(format!("Underruns: {}", self.underruns)),
+            self.stats_view(),
+            self.meter_view("Input", self.input_levels),
+            self.meter_view("Channel", self.channel_levels),
+            canvas::Canvas::new(ScopeView {
+                samples: &self.scope
+            })
+            .width(iced::Length::Fill)
+            .height(iced::Length::Fixed(60.0)),
+            if self.protection {
+                iced::widget::text("Limiter engaged").style(to_color(PALETTE[0]))
+            } else {
+                iced::widget::text("Limiter idle")
+            },
+            iced::widget::Column::with_children(
+                Param::ALL.iter().map(|&p| self.param_view(p)).collect()
+            )
+            .spacing(4),
+            iced::widget::text_input(
+                "Preset name, or full path",
+                &self.preset_path,
+                Message::PresetPathChanged
+            ),
+            self.preset_buttons(),
+            iced::widget::text(&self.preset_status),
         )
         .align_items(Alignment::Center)
         .padding(Padding::new(10.0))
-        .spacing(20)
-        .into()
+        .spacing(10);
+
+        iced::widget::scrollable(content).into()
     }
 
     fn hwnd(&self, hwnd: *mut std::ffi::c_void) {
@@ -266,6 +599,236 @@ impl iced::Application for UI {
     type Theme = iced::Theme;
 }
 
+impl UI {
+    fn selected_channel_info(&self) -> Option<ChannelInfo> {
+        self.selected_channel
+            .and_then(|id| self.available_channels.iter().find(|c| c.id == id))
+            .cloned()
+    }
+
+    /// Reset the name being edited to whatever the selected channel is called
+    fn update_channel_name(&mut self) {
+        self.channel_name = self
+            .selected_channel_info()
+            .map(|c| c.name)
+            .unwrap_or_default();
+    }
+
+    /// Every instance against every channel. Clicking a cell moves that instance
+    /// onto that channel, or off it if it is already there.
+    fn matrix_view(&self) -> iced::Element<'_, Message, iced::Renderer<iced::Theme>> {
+        let label_width = iced::Length::Fixed(90.0);
+
+        let header = iced::widget::row(
+            std::iter::once(iced::widget::text("").width(label_width).into())
+                .chain(self.available_channels.iter().map(|channel| {
+                    iced::widget::text(channel.to_string().chars().take(3).collect::<String>())
+                        .style(
+                            channel
+                                .colour
+                                .map(to_color)
+                                .unwrap_or(iced::Color::from_rgb(0.5, 0.5, 0.5)),
+                        )
+                        .width(iced::Length::Fixed(MATRIX_CELL_WIDTH))
+                        .into()
+                }))
+                .collect(),
+        )
+        .spacing(2);
+
+        let rows = self.instances.iter().map(|instance| {
+            let label = if Some(instance.id) == self.ours {
+                format!("{} (this)\n{}", instance, instance.mode)
+            } else {
+                format!("{}\n{}", instance, instance.mode)
+            };
+
+            iced::widget::row(
+                std::iter::once(iced::widget::text(label).width(label_width).into())
+                    .chain(self.available_channels.iter().map(|channel| {
+                        let connected = instance.channel == Some(channel.id);
+                        iced::widget::button(if connected { "●" } else { "·" })
+                            .style(if connected {
+                                iced::theme::Button::Primary
+                            } else {
+                                iced::theme::Button::Secondary
+                            })
+                            .on_press(Message::Connect(
+                                instance.id,
+                                (!connected).then_some(channel.id),
+                            ))
+                            .width(iced::Length::Fixed(MATRIX_CELL_WIDTH))
+                            .into()
+                    }))
+                    .collect(),
+            )
+            .align_items(Alignment::Center)
+            .spacing(2)
+            .into()
+        });
+
+        iced::widget::column(std::iter::once(header.into()).chain(rows).collect())
+            .spacing(2)
+            .into()
+    }
+
+    fn stats_view(&self) -> iced::Element<'_, Message, iced::Renderer<iced::Theme>> {
+        let Some(stats) = self.stats else {
+            return iced::widget::text("No channel").into();
+        };
+
+        iced::widget::column!(
+            iced::widget::text(format!(
+                "Sent {} blocks, dropped {}, {} underruns",
+                stats.blocks_sent, stats.blocks_dropped, stats.underruns
+            )),
+            iced::widget::text(format!(
+                "Missed {} blocks, repeated {}, {} seeks",
+                stats.blocks_missed, stats.blocks_repeated, stats.transport_jumps
+            )),
+            iced::widget::text(format!(
+                "Turned away {} instances for want of room",
+                stats.turned_away
+            )),
+            iced::widget::text(format!(
+                "Buffered {} samples (most {})",
+                stats.fill, stats.high_water
+            )),
+            iced::widget::text(match stats.sample_rate {
+                Some(rate) => format!("Sent at {} Hz", rate),
+                None => "Nothing sent yet".into(),
+            }),
+            iced::widget::text(format!("Clock drift {:+.0} ppm", self.drift_ppm)),
+        )
+        .align_items(Alignment::Center)
+        .spacing(2)
+        .into()
+    }
+
+    fn meter_view(
+        &self,
+        label: &str,
+        reading: Reading,
+    ) -> iced::Element<'_, Message, iced::Renderer<iced::Theme>> {
+        // Bar follows RMS, the number is the peak
+        let position = |level: f32| (1.0 - to_db(level) / METER_FLOOR_DB).clamp(0.0, 1.0);
+
+        iced::widget::row!(
+            iced::widget::text(label).width(iced::Length::Fixed(60.0)),
+            iced::widget::progress_bar(0.0..=1.0, position(reading.rms))
+                .height(iced::Length::Fixed(8.0)),
+            iced::widget::text(format!("{:.1} dB", to_db(reading.peak)))
+                .width(iced::Length::Fixed(60.0)),
+        )
+        .align_items(Alignment::Center)
+        .spacing(8)
+        .into()
+    }
+
+    /// Export and import, only clickable once there is something to export to or import from
+    fn preset_buttons(&self) -> iced::Element<'_, Message, iced::Renderer<iced::Theme>> {
+        let mut export = iced::widget::button("Export preset");
+        let mut import = iced::widget::button("Import preset");
+        if !self.preset_path.trim().is_empty() {
+            export = export.on_press(Message::ExportPreset);
+            import = import.on_press(Message::ImportPreset);
+        }
+
+        iced::widget::row!(export, import).spacing(8).into()
+    }
+
+    fn param_view(&self, param: Param) -> iced::Element<'_, Message, iced::Renderer<iced::Theme>> {
+        let value = self.params.get(param);
+
+        if param == Param::DelayUnit {
+            iced::widget::row!(
+                iced::widget::text(param.to_string()).width(iced::Length::Fixed(90.0)),
+                iced::widget::pick_list(
+                    &DelayUnit::ALL[..],
+                    Some(self.params.delay_unit()),
+                    move |unit| Message::ParamChanged(param, unit.to_normalised())
+                ),
+            )
+            .align_items(Alignment::Center)
+            .spacing(8)
+            .into()
+        } else if param.is_toggle() {
+            iced::widget::checkbox(param.to_string(), self.params.is_on(param), move |on| {
+                Message::ParamChanged(param, if on { 1.0 } else { 0.0 })
+            })
+            .into()
+        } else {
+            iced::widget::row!(
+                iced::widget::text(param.to_string()).width(iced::Length::Fixed(90.0)),
+                iced::widget::slider(0.0..=1.0, value, move |v| Message::ParamChanged(param, v))
+                    .step(0.001),
+                iced::widget::text(param.format(value, &self.params, self.sample_rate, self.tempo))
+                    .width(iced::Length::Fixed(140.0)),
+            )
+            .align_items(Alignment::Center)
+            .spacing(8)
+            .into()
+        }
+    }
+
+    fn send_to_plugin(&self, message: PluginMessage) -> iced::Command<Message> {
+        let host_message_tx = self.tx.clone();
+        iced::Command::perform(
+            async move { host_message_tx.send(message).await.unwrap() },
+            |_| Message::None,
+        )
+    }
+}
+
+fn to_color([r, g, b]: Colour) -> iced::Color {
+    iced::Color::from_rgb8(r, g, b)
+}
+
+fn to_db(level: f32) -> f32 {
+    (20.0 * level.log10()).max(METER_FLOOR_DB)
+}
+
+/// Draws the last stretch of channel audio
+struct ScopeView<'a> {
+    samples: &'a [f32],
+}
+
+impl canvas::Program<Message> for ScopeView<'_> {
+    type State = ();
+
+    fn draw(
+        &self,
+        _state: &Self::State,
+        _theme: &iced::Theme,
+        bounds: iced::Rectangle,
+        _cursor: canvas::Cursor,
+    ) -> Vec<canvas::Geometry> {
+        let mut frame = canvas::Frame::new(bounds.size());
+        let middle = bounds.height / 2.0;
+        let step = bounds.width / self.samples.len().saturating_sub(1).max(1) as f32;
+
+        let trace = canvas::Path::new(|path| {
+            for (i, s) in self.samples.iter().enumerate() {
+                let point = iced::Point::new(i as f32 * step, middle - s.clamp(-1.0, 1.0) * middle);
+                if i == 0 {
+                    path.move_to(point);
+                } else {
+                    path.line_to(point);
+                }
+            }
+        });
+
+        frame.stroke(
+            &trace,
+            canvas::Stroke::default()
+                .with_color(to_color(PALETTE[4]))
+                .with_width(1.0),
+        );
+
+        vec![frame.into_geometry()]
+    }
+}
+
 #[derive(Clone)]
 struct UIMessageWatcher {
     rx: Arc<tokio::sync::Mutex<mpsc::Receiver<UIMessage>>>,
@@ -0,0 +1,27 @@
+//! Putting our window inside the one the host gives us for the editor
+
+use crate::window_handle::WindowHandle;
+
+/// Show `window` inside `parent`, or hide it if `parent` is null
+#[cfg(windows)]
+pub fn embed(window: WindowHandle, parent: WindowHandle) {
+    use windows::Win32::UI::WindowsAndMessaging;
+
+    let show_cmd = if parent.is_valid() {
+        WindowsAndMessaging::SW_SHOW
+    } else {
+        WindowsAndMessaging::SW_HIDE
+    };
+
+    unsafe {
+        WindowsAndMessaging::SetParent(window.as_hwnd(), parent.as_hwnd());
+        WindowsAndMessaging::ShowWindow(window.as_hwnd(), show_cmd);
+    }
+}
+
+/// Show `window` inside `parent`, or hide it if `parent` is null
+#[cfg(not(windows))]
+pub fn embed(_window: WindowHandle, _parent: WindowHandle) {
+    // NOTE(emily): Nothing to reparent into here, the window is shown and hidden by
+    // iced itself. This is enough to run and test everything else on Linux.
+}
@@ -9,7 +9,7 @@ impl WindowHandle {
     }
 
     pub fn is_valid(&self) -> bool {
-        return self.0 != 0;
+        self.0 != 0
     }
 
     pub fn as_ptr(&self) -> Option<*mut c_void> {
@@ -19,6 +19,8 @@ impl WindowHandle {
             None
         }
     }
+
+    #[cfg(windows)]
     pub fn as_hwnd(&self) -> windows::Win32::Foundation::HWND {
         windows::Win32::Foundation::HWND(self.0)
     }
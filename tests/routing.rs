@@ -0,0 +1,813 @@
+use std::cell::{Cell, RefCell};
+
+use emilydotgg_feedback::{
+    dsp::{drift, limiter::SafetyLimiter},
+    engine::{Engine, Host},
+    layout::{self, Layout, Mixer, Port},
+    message::{PluginMessage, PluginStateChange},
+    params::{Param, Params},
+    router::{ring::MAX_SENDERS, SharedRouter, MAX_WIDE_CHANNELS},
+    AlignmentMode, Mode, Sample,
+};
+
+const SAMPLE_RATE: u32 = 44100;
+const BLOCK: usize = 64;
+const LATENCY: usize = 256;
+
+/// How long a block takes to play, in microseconds
+const BLOCK_MICROS: u64 = BLOCK as u64 * 1_000_000 / SAMPLE_RATE as u64;
+
+thread_local! {
+    /// The host's clock
+    static NOW: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Move the host's clock on to the next block period
+fn tick() {
+    NOW.with(|now| now.set(now.get() + BLOCK_MICROS));
+}
+
+/// Stands in for the plugin host and the editor, remembering what it was told
+#[derive(Default)]
+struct MockHost {
+    latency: Cell<u32>,
+    logs: RefCell<Vec<String>>,
+    changes: RefCell<Vec<PluginStateChange>>,
+    automated: RefCell<Vec<(Param, f32)>>,
+    location: Option<String>,
+}
+
+impl Host for MockHost {
+    fn log(&self, msg: String) {
+        self.logs.borrow_mut().push(msg);
+    }
+
+    fn set_latency(&self, latency: u32) {
+        self.latency.set(latency);
+    }
+
+    fn parameter_changed(&self, param: Param, value: f32) {
+        self.automated.borrow_mut().push((param, value));
+    }
+
+    fn notify(&self, change: PluginStateChange) {
+        self.changes.borrow_mut().push(change);
+    }
+
+    fn location(&self) -> Option<String> {
+        self.location.clone()
+    }
+
+    fn now(&self) -> u64 {
+        NOW.with(Cell::get)
+    }
+}
+
+fn engine(router: &SharedRouter, mode: Mode) -> Engine<MockHost> {
+    let mut engine = Engine::new(MockHost::default(), router.clone());
+    engine.set_sample_rate(SAMPLE_RATE);
+    engine.set_latency(LATENCY);
+    engine.set_mode(mode);
+    engine
+}
+
+/// Something recognisable and different in every sample and channel
+fn signal(seed: f32, len: usize) -> Vec<Sample> {
+    (0..len)
+        .map(|i| {
+            let t = i as f32 + seed;
+            [(t * 0.01).sin() * 0.5, (t * 0.013).cos() * 0.25]
+        })
+        .collect()
+}
+
+/// `input` as a receiver should hand it back: `delay` late, through the safety stage
+fn expected(input: &[Sample], delay: usize) -> Vec<Sample> {
+    let mut out = vec![[0.0; 2]; delay];
+    out.extend_from_slice(&input[..input.len() - delay]);
+    SafetyLimiter::new(Params::default().ceiling(), SAMPLE_RATE).process(&mut out);
+    out
+}
+
+/// Render every engine a block at a time, in order, as a host would
+fn run(engines: &mut [(&mut Engine<MockHost>, &[Sample])]) -> Vec<Vec<Sample>> {
+    let len = engines[0].1.len();
+    let mut outputs = vec![vec![[0.0; 2]; len]; engines.len()];
+
+    for start in (0..len).step_by(BLOCK) {
+        tick();
+        for ((engine, input), output) in engines.iter_mut().zip(outputs.iter_mut()) {
+            engine.render(
+                &input[start..start + BLOCK],
+                &mut output[start..start + BLOCK],
+            );
+        }
+    }
+
+    outputs
+}
+
+#[test]
+fn sender_to_receiver_is_sample_exact() {
+    let router = SharedRouter::new_private();
+    let mut sender = engine(&router, Mode::Sender);
+    let channel = sender.new_channel().unwrap();
+    let mut receiver = engine(&router, Mode::Receiver);
+    receiver.set_channel(channel);
+
+    let input = signal(0.0, BLOCK * 64);
+    let silence = vec![[0.0; 2]; input.len()];
+    let outputs = run(&mut [(&mut sender, &input), (&mut receiver, &silence)]);
+
+    assert!(outputs[0].iter().all(|s| *s == [0.0; 2]));
+    assert_eq!(outputs[1], expected(&input, LATENCY));
+}
+
+#[test]
+fn receiver_rendering_first_is_sample_exact() {
+    let router = SharedRouter::new_private();
+    let mut sender = engine(&router, Mode::Sender);
+    let channel = sender.new_channel().unwrap();
+    let mut receiver = engine(&router, Mode::Receiver);
+    receiver.set_channel(channel);
+
+    let input = signal(0.0, BLOCK * 64);
+    let silence = vec![[0.0; 2]; input.len()];
+    let outputs = run(&mut [(&mut receiver, &silence), (&mut sender, &input)]);
+
+    assert_eq!(outputs[0], expected(&input, LATENCY));
+}
+
+#[test]
+fn senders_are_mixed() {
+    let router = SharedRouter::new_private();
+    let mut a = engine(&router, Mode::Sender);
+    let channel = a.new_channel().unwrap();
+    let mut b = engine(&router, Mode::Sender);
+    b.set_channel(channel);
+    let mut receiver = engine(&router, Mode::Receiver);
+    receiver.set_channel(channel);
+
+    let (input_a, input_b) = (signal(0.0, BLOCK * 64), signal(1000.0, BLOCK * 64));
+    let silence = vec![[0.0; 2]; input_a.len()];
+    let outputs = run(&mut [
+        (&mut a, &input_a),
+        (&mut b, &input_b),
+        (&mut receiver, &silence),
+    ]);
+
+    let mixed: Vec<Sample> = input_a
+        .iter()
+        .zip(&input_b)
+        .map(|(a, b)| [a[0] + b[0], a[1] + b[1]])
+        .collect();
+    assert_eq!(outputs[2], expected(&mixed, LATENCY));
+}
+
+#[test]
+fn every_receiver_gets_a_copy() {
+    let router = SharedRouter::new_private();
+    let mut sender = engine(&router, Mode::Sender);
+    let channel = sender.new_channel().unwrap();
+    let mut first = engine(&router, Mode::Receiver);
+    first.set_channel(channel);
+    let mut second = engine(&router, Mode::Receiver);
+    second.set_channel(channel);
+
+    let input = signal(0.0, BLOCK * 64);
+    let silence = vec![[0.0; 2]; input.len()];
+    let outputs = run(&mut [
+        (&mut sender, &input),
+        (&mut first, &silence),
+        (&mut second, &silence),
+    ]);
+
+    assert_eq!(outputs[1], expected(&input, LATENCY));
+    assert_eq!(outputs[2], expected(&input, LATENCY));
+}
+
+#[test]
+fn tap_passes_input_through() {
+    let router = SharedRouter::new_private();
+    let mut tap = engine(&router, Mode::Tap);
+    let channel = tap.new_channel().unwrap();
+    let mut receiver = engine(&router, Mode::Receiver);
+    receiver.set_channel(channel);
+
+    let input = signal(0.0, BLOCK * 64);
+    let silence = vec![[0.0; 2]; input.len()];
+    let outputs = run(&mut [(&mut tap, &input), (&mut receiver, &silence)]);
+
+    assert_eq!(outputs[0], input);
+    assert_eq!(outputs[1], expected(&input, LATENCY));
+}
+
+#[test]
+fn dry_return_is_delayed_input() {
+    let router = SharedRouter::new_private();
+    let mut sender = engine(&router, Mode::Sender);
+    let channel = sender.new_channel().unwrap();
+    let mut ret = engine(&router, Mode::Return);
+    ret.set_channel(channel);
+    ret.set_param(Param::Mix, 0.0, false);
+
+    let (input, dry) = (signal(0.0, BLOCK * 64), signal(500.0, BLOCK * 64));
+    let outputs = run(&mut [(&mut sender, &input), (&mut ret, &dry)]);
+
+    // Give the mix time to settle before we look
+    let settled = BLOCK * 32;
+    assert_eq!(
+        outputs[1][settled..],
+        dry[settled - LATENCY..dry.len() - LATENCY]
+    );
+}
+
+#[test]
+fn latency_is_reported() {
+    let router = SharedRouter::new_private();
+    let mut engine = engine(&router, Mode::Receiver);
+    assert_eq!(engine.host().latency.get(), LATENCY as u32);
+
+    engine.set_mode(Mode::Sender);
+    assert_eq!(engine.host().latency.get(), 0);
+}
+
+#[test]
+fn ui_changes_are_automated() {
+    let router = SharedRouter::new_private();
+    let mut engine = engine(&router, Mode::Receiver);
+
+    engine.set_param(Param::Mute, 1.0, false);
+    engine.set_param(Param::Mix, 0.5, true);
+
+    assert_eq!(*engine.host().automated.borrow(), vec![(Param::Mute, 1.0)]);
+}
+
+#[test]
+fn state_restores_routing() {
+    let router = SharedRouter::new_private();
+    let mut sender = engine(&router, Mode::Sender);
+    let channel = sender.new_channel().unwrap();
+    sender.set_param(Param::Invert, 1.0, false);
+
+    let mut restored = Engine::new(MockHost::default(), router.clone());
+    restored.restore(sender.state());
+
+    assert_eq!(restored.mode(), Mode::Sender);
+    assert_eq!(restored.channel(), Some(channel));
+    assert_eq!(restored.params(), sender.params());
+}
+
+#[test]
+fn instances_keep_their_id_across_a_reload() {
+    let router = SharedRouter::new_private();
+    let sender = engine(&router, Mode::Sender);
+    let (id, state) = (sender.instance(), sender.state());
+
+    // A copy made while the original is still around is somebody new
+    let mut copy = Engine::new(MockHost::default(), router.clone());
+    copy.restore(state.clone());
+    assert_ne!(copy.instance(), id);
+
+    drop(sender);
+    let mut reloaded = Engine::new(MockHost::default(), router.clone());
+    let new_id = reloaded.instance();
+    reloaded.restore(state);
+    assert_eq!(reloaded.instance(), id);
+
+    let listed: Vec<_> = router.instances().into_iter().map(|i| i.id).collect();
+    assert!(listed.contains(&id));
+    assert!(!listed.contains(&new_id));
+}
+
+#[test]
+fn unnamed_instances_go_by_where_the_host_put_them() {
+    let router = SharedRouter::new_private();
+    let host = MockHost {
+        location: Some("Insert 4".into()),
+        ..Default::default()
+    };
+    let mut engine = Engine::new(host, router.clone());
+
+    let find = |id| router.instances().into_iter().find(|i| i.id == id).unwrap();
+    assert_eq!(find(engine.instance()).label, "Insert 4");
+
+    engine.set_label("Drum bus".into());
+    assert_eq!(find(engine.instance()).label, "Drum bus");
+}
+
+#[test]
+fn only_our_projects_channels_are_saved() {
+    let router = SharedRouter::new_private();
+    let mut sender = engine(&router, Mode::Sender);
+    let ours = sender.new_channel().unwrap();
+    let mut receiver = engine(&router, Mode::Receiver);
+    receiver.set_channel(ours);
+
+    // In use, but by nobody in our project
+    let theirs = router.new_channel().unwrap();
+    let _their_sender = router.add_sender(&theirs).unwrap();
+
+    let graph = sender.state().graph;
+    assert_eq!(graph.len(), 1);
+    assert_eq!(graph[0].channel.info.id, ours);
+    assert!(graph[0]
+        .members
+        .contains(&(sender.instance(), Mode::Sender)));
+    assert!(graph[0]
+        .members
+        .contains(&(receiver.instance(), Mode::Receiver)));
+}
+
+#[test]
+fn stats_count_blocks() {
+    let router = SharedRouter::new_private();
+    let mut sender = engine(&router, Mode::Sender);
+    let channel = sender.new_channel().unwrap();
+    let mut receiver = engine(&router, Mode::Receiver);
+    receiver.set_channel(channel);
+
+    let input = signal(0.0, BLOCK * 64);
+    let silence = vec![[0.0; 2]; input.len()];
+    run(&mut [(&mut sender, &input), (&mut receiver, &silence)]);
+
+    let stats = router.channel_stats(&channel).unwrap();
+    assert_eq!(stats.blocks_sent, 64);
+    assert_eq!(stats.blocks_dropped, 0);
+    assert_eq!(stats.underruns, 0);
+    assert_eq!(stats.blocks_missed, 0);
+    assert_eq!(stats.blocks_repeated, 0);
+    assert_eq!(stats.transport_jumps, 0);
+    // Some of the latency is spent in the receiver's resampler rather than the channel
+    assert_eq!(stats.fill, LATENCY - drift::DELAY);
+    assert_eq!(stats.high_water, LATENCY - drift::DELAY + BLOCK);
+}
+
+#[test]
+fn stats_count_drops_and_underruns() {
+    let router = SharedRouter::new_private();
+    let mut sender = engine(&router, Mode::Sender);
+    let channel = sender.new_channel().unwrap();
+    let mut receiver = engine(&router, Mode::Receiver);
+    receiver.set_channel(channel);
+
+    let input = signal(0.0, BLOCK * 16);
+    let silence = vec![[0.0; 2]; input.len()];
+    run(&mut [(&mut sender, &input), (&mut receiver, &silence)]);
+
+    // The receiver stops for a while, then carries on after the sender has stopped
+    run(&mut [(&mut sender, &input)]);
+    run(&mut [(&mut receiver, &silence)]);
+
+    let stats = router.channel_stats(&channel).unwrap();
+    assert_eq!(stats.blocks_sent, 32);
+    assert_eq!(stats.blocks_dropped, 1);
+    assert!(stats.underruns > 0);
+    assert!(stats.high_water > LATENCY);
+    // Skipped forward past what it missed, then back over what it had already heard
+    assert!(stats.blocks_missed > 0);
+    assert!(stats.blocks_repeated > 0);
+}
+
+#[test]
+fn full_channels_turn_senders_away_once() {
+    let router = SharedRouter::new_private();
+    let channel = router.new_channel().unwrap();
+    let _senders: Vec<_> = (0..MAX_SENDERS)
+        .map(|_| router.add_sender(&channel).unwrap())
+        .collect();
+
+    let mut sender = engine(&router, Mode::Sender);
+    sender.set_channel(channel);
+    run(&mut [(&mut sender, &signal(0.0, BLOCK * 16))]);
+
+    let stats = router.channel_stats(&channel).unwrap();
+    assert_eq!(stats.turned_away, 1);
+    assert_eq!(stats.blocks_dropped, 0);
+}
+
+#[test]
+fn meters_follow_the_signal() {
+    let router = SharedRouter::new_private();
+    let mut sender = engine(&router, Mode::Sender);
+    sender.new_channel().unwrap();
+    let meters = sender.meters();
+
+    let input = signal(0.0, BLOCK * 64);
+    let mut output = vec![[0.0; 2]; BLOCK];
+    for block in input.chunks(BLOCK) {
+        sender.render(block, &mut output);
+    }
+
+    let levels = meters.input.read();
+    assert!(levels.peak > 0.4 && levels.peak <= 0.5);
+    assert!(levels.rms > 0.0 && levels.rms < levels.peak);
+    assert_eq!(meters.channel.read(), levels);
+
+    // What went out last, in mono
+    let mut scope = vec![];
+    meters.scope.read(&mut scope);
+    let last = input.last().unwrap();
+    assert_eq!(*scope.last().unwrap(), (last[0] + last[1]) * 0.5);
+}
+
+#[test]
+fn instances_are_listed_and_can_be_moved() {
+    let router = SharedRouter::new_private();
+    let mut sender = engine(&router, Mode::Sender);
+    let channel = sender.new_channel().unwrap();
+    sender.set_label("Insert 3".into());
+    let mut receiver = engine(&router, Mode::Receiver);
+
+    let find = |id| router.instances().into_iter().find(|i| i.id == id).unwrap();
+
+    let listed = find(sender.instance());
+    assert_eq!(listed.label, "Insert 3");
+    assert_eq!(listed.mode, Mode::Sender);
+    assert_eq!(listed.channel, Some(channel));
+    assert_eq!(find(receiver.instance()).channel, None);
+
+    // Clicked on in the sender's matrix, picked up by the receiver
+    sender.handle_message(PluginMessage::Connect(receiver.instance(), Some(channel)));
+    receiver.housekeeping();
+    assert_eq!(receiver.channel(), Some(channel));
+    assert_eq!(find(receiver.instance()).channel, Some(channel));
+
+    sender.handle_message(PluginMessage::Connect(receiver.instance(), None));
+    receiver.housekeeping();
+    assert_eq!(receiver.channel(), None);
+
+    let id = receiver.instance();
+    drop(receiver);
+    assert!(router.instances().iter().all(|i| i.id != id));
+}
+
+#[test]
+fn drift_is_soaked_up() {
+    let router = SharedRouter::new_private();
+    let mut sender = engine(&router, Mode::Sender);
+    let channel = sender.new_channel().unwrap();
+    let mut receiver = engine(&router, Mode::Receiver);
+    receiver.set_channel(channel);
+
+    // A sender whose clock runs 500ppm fast would overflow a receiver that
+    // couldn't keep up within a few seconds
+    let rate = 1.0005;
+    let input = signal(0.0, BLOCK * 2);
+    let mut output = vec![[0.0; 2]; BLOCK * 2];
+    let mut owed = 0.0;
+    for _ in 0..(SAMPLE_RATE as usize * 30 / BLOCK) {
+        owed += BLOCK as f64 * rate;
+        let len = owed as usize;
+        owed -= len as f64;
+
+        tick();
+        sender.render(&input[..len], &mut output[..len]);
+        receiver.render(&input[..BLOCK], &mut output[..BLOCK]);
+    }
+
+    let stats = router.channel_stats(&channel).unwrap();
+    assert_eq!(stats.blocks_dropped, 0);
+    assert_eq!(stats.underruns, 0);
+    assert!((receiver.drift_ppm() - 500.0).abs() < 50.0);
+
+    // Still sitting where it should be
+    let target = (LATENCY - drift::DELAY) as f64;
+    assert!((stats.fill as f64 - target).abs() < 8.0, "{}", stats.fill);
+}
+
+#[test]
+fn latency_covers_the_resampler() {
+    let router = SharedRouter::new_private();
+    let mut sender = engine(&router, Mode::Sender);
+    sender.set_sample_rate(8000);
+    let channel = sender.new_channel().unwrap();
+    let mut receiver = engine(&router, Mode::Receiver);
+    receiver.set_sample_rate(192000);
+    receiver.set_channel(channel);
+
+    // NOTE(emily): LATENCY at 192kHz is far less than the resampler holds on to at 8kHz
+    let input = signal(0.0, BLOCK);
+    let mut output = vec![[0.0; 2]; BLOCK];
+    let mut owed = 0.0;
+    for i in 0..256 {
+        owed += BLOCK as f64 * 8000.0 / 192000.0;
+        let len = owed as usize;
+        owed -= len as f64;
+
+        tick();
+        sender.render(&input[..len], &mut output[..len]);
+        receiver.render(&input, &mut output);
+
+        if i == 0 {
+            receiver.housekeeping();
+        }
+    }
+
+    let reported = receiver.host().latency.get() as usize;
+    assert!(reported > LATENCY);
+    assert_eq!(reported, receiver.current_latency());
+}
+
+#[test]
+fn different_rates_are_converted() {
+    let router = SharedRouter::new_private();
+    let mut sender = engine(&router, Mode::Sender);
+    sender.set_sample_rate(48000);
+    let channel = sender.new_channel().unwrap();
+    let mut receiver = engine(&router, Mode::Receiver);
+    receiver.set_channel(channel);
+
+    // 1kHz at 48kHz, which would come out at nearly 1.1kHz if it weren't converted
+    let tone: Vec<Sample> = (0..BLOCK * 2)
+        .map(|i| {
+            let s = (std::f32::consts::TAU * 1000.0 * i as f32 / 48000.0).sin() * 0.5;
+            [s, s]
+        })
+        .collect();
+
+    let mut output = vec![];
+    let mut block = vec![[0.0; 2]; BLOCK * 2];
+    let silence = vec![[0.0; 2]; BLOCK];
+    let (mut owed, mut sent) = (0.0, 0);
+    for i in 0..(SAMPLE_RATE as usize * 5 / BLOCK) {
+        owed += BLOCK as f64 * 48000.0 / SAMPLE_RATE as f64;
+        let len = owed as usize;
+        owed -= len as f64;
+
+        let input: Vec<Sample> = (sent..sent + len).map(|j| tone[j % 48]).collect();
+        sent += len;
+        tick();
+        sender.render(&input, &mut block[..len]);
+
+        receiver.render(&silence, &mut block[..BLOCK]);
+        output.extend_from_slice(&block[..BLOCK]);
+
+        // Give the receiver a chance to notice the sender's rate
+        if i == 0 {
+            receiver.housekeeping();
+        }
+    }
+
+    let stats = router.channel_stats(&channel).unwrap();
+    assert_eq!(stats.sample_rate, Some(48000));
+    assert_eq!(stats.blocks_dropped, 0);
+    assert_eq!(stats.underruns, 0);
+
+    // Count rising zero crossings over the last second
+    let last = &output[output.len() - SAMPLE_RATE as usize..];
+    let crossings = last
+        .windows(2)
+        .filter(|w| w[0][0] < 0.0 && w[1][0] >= 0.0)
+        .count();
+    assert!((crossings as i32 - 1000).abs() <= 2, "{}", crossings);
+
+    let peak = last.iter().map(|s| s[0].abs()).fold(0.0, f32::max);
+    assert!((peak - 0.5).abs() < 0.01, "{}", peak);
+}
+
+#[test]
+fn mono_channel_is_mixed_down_and_back_up() {
+    let router = SharedRouter::new_private();
+    let mut sender = engine(&router, Mode::Sender);
+    let channel = sender.new_channel().unwrap();
+    router.set_channel_layout(&channel, Layout::Mono);
+    let mut receiver = engine(&router, Mode::Receiver);
+    receiver.set_channel(channel);
+
+    let input = signal(0.0, BLOCK * 64);
+    let silence = vec![[0.0; 2]; input.len()];
+    let outputs = run(&mut [(&mut sender, &input), (&mut receiver, &silence)]);
+
+    let mono: Vec<Sample> = input
+        .iter()
+        .map(|s| {
+            let m = s[0] * 0.5 + s[1] * 0.5;
+            [m, m]
+        })
+        .collect();
+    assert_eq!(outputs[1], expected(&mono, LATENCY));
+}
+
+#[test]
+fn pairs_carry_surround_stems() {
+    let router = SharedRouter::new_private();
+    let channel = router.new_channel().unwrap();
+    router.set_channel_layout(&channel, Layout::Surround51);
+
+    let mut senders: Vec<_> = (0..3)
+        .map(|p| {
+            let mut sender = engine(&router, Mode::Sender);
+            sender.set_port(Port::Pair(p));
+            sender.set_channel(channel);
+            sender
+        })
+        .collect();
+
+    let mut surrounds = engine(&router, Mode::Receiver);
+    surrounds.set_port(Port::Pair(2));
+    surrounds.set_channel(channel);
+    let mut folded = engine(&router, Mode::Receiver);
+    folded.set_channel(channel);
+
+    let inputs: Vec<_> = (0..3)
+        .map(|p| signal(p as f32 * 1000.0, BLOCK * 64))
+        .collect();
+    let silence = vec![[0.0; 2]; inputs[0].len()];
+    let [a, b, c] = &mut senders[..] else {
+        unreachable!()
+    };
+    let outputs = run(&mut [
+        (a, &inputs[0]),
+        (b, &inputs[1]),
+        (c, &inputs[2]),
+        (&mut surrounds, &silence),
+        (&mut folded, &silence),
+    ]);
+
+    assert_eq!(outputs[3], expected(&inputs[2], LATENCY));
+
+    let mixer = Mixer::receiving(Port::Mix, Layout::Surround51);
+    let downmix: Vec<Sample> = (0..inputs[0].len())
+        .map(|i| {
+            let mut frame = [0.0; layout::MAX_WIDTH];
+            for (p, input) in inputs.iter().enumerate() {
+                frame[p * 2..p * 2 + 2].copy_from_slice(&input[i]);
+            }
+            layout::to_sample(&mixer.mix(&frame))
+        })
+        .collect();
+    assert_eq!(outputs[4], expected(&downmix, LATENCY));
+}
+
+#[test]
+fn only_so_many_channels_carry_more_than_stereo() {
+    let router = SharedRouter::new_private();
+    let channels: Vec<_> = (0..=MAX_WIDE_CHANNELS)
+        .map(|_| router.new_channel().unwrap())
+        .collect();
+    for channel in &channels[..MAX_WIDE_CHANNELS] {
+        assert!(router.set_channel_layout(channel, Layout::Surround51));
+    }
+
+    let last = channels[MAX_WIDE_CHANNELS];
+    assert!(!router.set_channel_layout(&last, Layout::Quad));
+    assert_eq!(router.channel_info(&last).unwrap().layout, Layout::Stereo);
+    assert!(router.set_channel_layout(&last, Layout::Mono));
+
+    // Room comes back once a wide channel is gone
+    router.delete_channel(&channels[0]);
+    assert!(router.set_channel_layout(&last, Layout::Quad));
+}
+
+#[test]
+fn seeks_mute_stale_audio() {
+    let router = SharedRouter::new_private();
+    let mut sender = engine(&router, Mode::Sender);
+    let channel = sender.new_channel().unwrap();
+    let mut receiver = engine(&router, Mode::Receiver);
+    receiver.set_channel(channel);
+
+    let input = signal(0.0, BLOCK * 64);
+    let silence = vec![[0.0; 2]; input.len()];
+    let seek = input.len() / 2;
+
+    sender.set_song_position(Some(0));
+    let mut outputs = run(&mut [
+        (&mut sender, &input[..seek]),
+        (&mut receiver, &silence[..seek]),
+    ]);
+    sender.set_song_position(Some(44100 * 60));
+    let after = run(&mut [
+        (&mut sender, &input[seek..]),
+        (&mut receiver, &silence[seek..]),
+    ]);
+    outputs[1].extend_from_slice(&after[1]);
+
+    // Whatever was sent before the seek and not yet heard is dropped, apart from
+    // the little the receiver's resampler had already taken in
+    let mut heard = input.clone();
+    heard[seek - LATENCY + drift::DELAY..seek].fill([0.0; 2]);
+    assert_eq!(outputs[1], expected(&heard, LATENCY));
+
+    let stats = router.channel_stats(&channel).unwrap();
+    assert_eq!(stats.transport_jumps, 1);
+    assert_eq!(stats.blocks_missed, 0);
+    assert_eq!(stats.blocks_repeated, 0);
+}
+
+#[test]
+fn host_song_positions_only_jump_when_seeked() {
+    let router = SharedRouter::new_private();
+    let mut sender = engine(&router, Mode::Sender);
+    let channel = sender.new_channel().unwrap();
+
+    let input = signal(0.0, BLOCK);
+    let mut output = vec![[0.0; 2]; BLOCK];
+    let jumps = || router.channel_stats(&channel).unwrap().transport_jumps;
+
+    // The host only knows where its song is to within a sample or so
+    for block in 0..64 {
+        sender.follow_song_position(Some(block * BLOCK as u64 + block % 3));
+        sender.render(&input, &mut output);
+    }
+    assert_eq!(jumps(), 0);
+
+    sender.follow_song_position(Some(SAMPLE_RATE as u64 * 60));
+    sender.render(&input, &mut output);
+    assert_eq!(jumps(), 1);
+
+    // Blocks the host couldn't say anything about carry on from where it last did
+    for _ in 0..4 {
+        sender.render(&input, &mut output);
+    }
+    sender.follow_song_position(Some(SAMPLE_RATE as u64 * 60 + 5 * BLOCK as u64));
+    sender.render(&input, &mut output);
+    assert_eq!(jumps(), 1);
+
+    // Stopping and starting again isn't a seek
+    sender.follow_song_position(None);
+    sender.render(&input, &mut output);
+    sender.follow_song_position(Some(0));
+    sender.render(&input, &mut output);
+    assert_eq!(jumps(), 1);
+}
+
+#[test]
+fn transport_alignment_ignores_render_order() {
+    for sender_first in [true, false] {
+        let router = SharedRouter::new_private();
+        let mut sender = engine(&router, Mode::Sender);
+        let channel = sender.new_channel().unwrap();
+        let mut receiver = engine(&router, Mode::Receiver);
+        receiver.set_channel(channel);
+        receiver.set_alignment(AlignmentMode::Transport);
+        sender.set_song_position(Some(0));
+        receiver.set_song_position(Some(0));
+
+        let input = signal(0.0, BLOCK * 64);
+        let silence = vec![[0.0; 2]; input.len()];
+        let output = if sender_first {
+            run(&mut [(&mut sender, &input), (&mut receiver, &silence)]).remove(1)
+        } else {
+            run(&mut [(&mut receiver, &silence), (&mut sender, &input)]).remove(0)
+        };
+
+        assert_eq!(output, expected(&input, LATENCY));
+    }
+}
+
+#[test]
+fn transport_alignment_follows_the_host() {
+    let router = SharedRouter::new_private();
+    let mut sender = engine(&router, Mode::Sender);
+    let channel = sender.new_channel().unwrap();
+    let mut receiver = engine(&router, Mode::Receiver);
+    receiver.set_channel(channel);
+    receiver.set_alignment(AlignmentMode::Transport);
+
+    let input = signal(0.0, BLOCK * 64);
+    let silence = vec![[0.0; 2]; input.len()];
+    let mut output = vec![[0.0; 2]; input.len()];
+
+    // As a host would, saying where its song is every block, give or take a sample.
+    // This host is slow enough that the time doesn't tell us the sender rendered in
+    // the same block period as us.
+    for (block, start) in (0..input.len()).step_by(BLOCK).enumerate() {
+        let position = start as u64 + block as u64 % 2;
+        sender.follow_song_position(Some(position));
+        sender.render(&input[start..start + BLOCK], &mut [[0.0; 2]; BLOCK]);
+        tick();
+        receiver.follow_song_position(Some(position));
+        receiver.render(
+            &silence[start..start + BLOCK],
+            &mut output[start..start + BLOCK],
+        );
+    }
+
+    // Left to queue up, this would come out a block early
+    assert_eq!(output, expected(&input, LATENCY));
+}
+
+#[test]
+fn channels_made_again_are_joined_again() {
+    let router = SharedRouter::new_private();
+    let mut sender = engine(&router, Mode::Sender);
+    let channel = sender.new_channel().unwrap();
+    let mut receiver = engine(&router, Mode::Receiver);
+    receiver.set_channel(channel);
+
+    // e.g. by another instance loading a project that had it
+    router.delete_channel(&channel);
+    router.new_channel_with_id(&channel);
+    sender.housekeeping();
+    receiver.housekeeping();
+
+    let input = signal(0.0, BLOCK * 64);
+    let silence = vec![[0.0; 2]; input.len()];
+    let outputs = run(&mut [(&mut sender, &input), (&mut receiver, &silence)]);
+
+    assert_eq!(sender.channel(), Some(channel));
+    assert_eq!(receiver.channel(), Some(channel));
+    assert_eq!(outputs[1], expected(&input, LATENCY));
+}